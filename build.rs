@@ -1,3 +1,5 @@
+use std::env;
+use std::fs;
 use std::path::PathBuf;
 
 use embuild::{
@@ -7,6 +9,11 @@ use embuild::{
 };
 
 fn main() -> anyhow::Result<()> {
+    // The simulator builds for the host, where there is no ESP-IDF to configure.
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("espidf") {
+        return Ok(());
+    }
+
     // Necessary because of this issue: https://github.com/rust-lang/cargo/issues/9641
     LinkArgs::output_propagated("ESP_IDF")?;
 
@@ -27,7 +34,29 @@ fn main() -> anyhow::Result<()> {
         cargo::track_file(ulp_sym);
     }
 
+    embed_certificates()?;
+
     cfg.output();
 
     Ok(())
 }
+
+/// Copy the PEM files named by these variables into OUT_DIR, where the MQTT
+/// client can include them. Unset ones are left empty.
+fn embed_certificates() -> anyhow::Result<()> {
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
+    for name in ["MQTT_CA_CERT", "MQTT_CLIENT_CERT", "MQTT_CLIENT_KEY"] {
+        println!("cargo:rerun-if-env-changed={name}");
+        let data = match env::var(name) {
+            Ok(path) => {
+                cargo::track_file(&path);
+                fs::read(path)?
+            }
+            Err(_) => Vec::new(),
+        };
+        fs::write(out_dir.join(format!("{}.pem", name.to_lowercase())), data)?;
+    }
+
+    Ok(())
+}
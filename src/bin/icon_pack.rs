@@ -0,0 +1,38 @@
+//! Build an icon pack for the `assets` partition from a directory of
+//! `<name>_<state>_64x64.tga` images, for example:
+//!
+//! ```sh
+//! cargo run --no-default-features --features simulator --bin icon_pack -- src/display/images icons.bin
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use anyhow::bail;
+use anyhow::Result;
+
+use robotica_remote::pack;
+
+/// Size of the `assets` partition in `partitions.csv`.
+const PARTITION_SIZE: usize = 960 * 1024;
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let [_, images, output] = args.as_slice() else {
+        bail!("Usage: icon_pack <images directory> <output file>");
+    };
+
+    let data = pack::build_from_dir(Path::new(images))?;
+    if data.len() > PARTITION_SIZE {
+        bail!(
+            "Icon pack is {} bytes, but the assets partition only holds {PARTITION_SIZE}",
+            data.len()
+        );
+    }
+
+    let count = pack::Pack::parse(&data)?.names().count();
+    fs::write(output, &data)?;
+    println!("Wrote {count} icons in {} bytes to {output}", data.len());
+    Ok(())
+}
@@ -30,13 +30,18 @@ impl Board for Lca2022Badge {
     fn get_display(&self) -> mpsc::Sender<display::DisplayCommand> {
         self.display.clone()
     }
+
+    fn get_rssi(&self) -> Option<i8> {
+        wifi::esp::get_rssi(&self.wifi)
+    }
 }
 
 pub fn configure_devices(tx: mpsc::Sender<messages::Message>) -> Result<Lca2022Badge> {
     let peripherals = Peripherals::take().unwrap();
     let pins = peripherals.pins;
 
-    let display = display::lca2021_badge::connect(peripherals.i2c0, pins.gpio4, pins.gpio5)?;
+    let display =
+        display::lca2021_badge::connect(peripherals.i2c0, pins.gpio4, pins.gpio5, tx.clone())?;
 
     let (wifi, sntp) = wifi::esp::connect(peripherals.modem)?;
 
@@ -6,13 +6,16 @@ use anyhow::Result;
 
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::Rectangle;
-use esp_idf_hal::gpio::PinDriver;
+use esp_idf_hal::ledc::config::TimerConfig;
+use esp_idf_hal::ledc::LedcDriver;
+use esp_idf_hal::ledc::LedcTimerDriver;
 use esp_idf_hal::prelude::*;
 use esp_idf_svc::sntp::EspSntp;
 use esp_idf_svc::wifi::EspWifi;
 
 use crate::button::ButtonId;
 use crate::display;
+use crate::display::makerfab::Backlight;
 use crate::display::makerfab::NUM_PER_PAGE;
 use crate::messages;
 use crate::wifi;
@@ -33,6 +36,10 @@ impl Board for Makerfab {
     fn get_display(&self) -> mpsc::Sender<display::DisplayCommand> {
         self.display.clone()
     }
+
+    fn get_rssi(&self) -> Option<i8> {
+        wifi::esp::get_rssi(&self.wifi)
+    }
 }
 
 pub struct ButtonInfo {
@@ -44,8 +51,15 @@ pub fn configure_devices(tx: mpsc::Sender<messages::Message>) -> Result<Makerfab
     let peripherals = Peripherals::take().unwrap();
     let pins = peripherals.pins;
 
-    let backlight = PinDriver::output(pins.gpio5)?;
-    // backlight.set_low().unwrap();
+    let timer = LedcTimerDriver::new(
+        peripherals.ledc.timer0,
+        &TimerConfig::new().frequency(25.kHz().into()),
+    )?;
+    let backlight = Backlight::new(LedcDriver::new(
+        peripherals.ledc.channel0,
+        timer,
+        pins.gpio5,
+    )?);
 
     let buttons: [ButtonInfo; NUM_PER_PAGE] = [
         ButtonInfo {
@@ -108,15 +122,15 @@ pub fn configure_devices(tx: mpsc::Sender<messages::Message>) -> Result<Makerfab
         pins.gpio15,
         backlight,
         &buttons,
-    )
-    .unwrap();
+        tx.clone(),
+    )?;
 
     let (wifi, sntp) = wifi::esp::connect(peripherals.modem)?;
 
     let sda = pins.gpio26;
     let scl = pins.gpio27;
     let i2c1 = peripherals.i2c1;
-    touchscreen::connect(i2c1, sda, scl, buttons, tx);
+    touchscreen::connect(i2c1, sda, scl, buttons, tx)?;
 
     Ok(Makerfab {
         wifi,
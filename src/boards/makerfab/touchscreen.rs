@@ -1,5 +1,7 @@
 use std::{thread, time::Duration};
 
+use anyhow::anyhow;
+use anyhow::Result;
 use esp_idf_hal::{
     gpio::{InputPin, OutputPin},
     i2c,
@@ -12,6 +14,7 @@ use crate::{
     button::ButtonId,
     display::makerfab::NUM_PER_PAGE,
     messages::{self, Message},
+    supervisor,
 };
 
 use super::ButtonInfo;
@@ -38,75 +41,90 @@ fn get_button_for_point(buttons: &[ButtonInfo], p: TouchPoint) -> Option<&Button
     None
 }
 
+type TouchScreen = Ft6x36<i2c::I2cDriver<'static>>;
+
+fn poll(
+    touch_screen: &mut TouchScreen,
+    buttons: &[ButtonInfo; NUM_PER_PAGE],
+    pressed: &mut Option<ButtonId>,
+    tx: &messages::Sender,
+) -> Result<()> {
+    // Start again from scratch, in case the failure was the touch screen.
+    touch_screen
+        .init()
+        .map_err(|err| anyhow!("Could not initialize touch screen: {err:?}"))?;
+    match touch_screen.get_info() {
+        Some(info) => info!("Touch screen info: {info:?}"),
+        None => warn!("No info"),
+    }
+
+    loop {
+        // match touch_screen.get_diagnostics() {
+        //     Ok(diagnostics) => println!("Touch screen info: {diagnostics:?}"),
+        //     Err(err) => println!("No info: {err}"),
+        // }
+
+        let x = touch_screen
+            .get_touch_event()
+            .map_err(|err| anyhow!("Could not read touch screen: {err:?}"))?;
+        // println!("get_touch_event: {x:?}");
+
+        let button_id = match x.p1 {
+            Some(p1) => {
+                let p1 = translate(p1);
+                let button = get_button_for_point(buttons, p1);
+                match button {
+                    Some(button) => Some(button.id),
+                    None => Some(ButtonId::NotAButton),
+                }
+            }
+            None => None,
+        };
+
+        let (do_release, do_press) = match (*pressed, button_id) {
+            (None, None) => (None, None),
+            (None, Some(button_id)) => (None, Some(button_id)),
+            (Some(button_id), None) => (Some(button_id), None),
+            (Some(p), Some(r)) if p == r => (None, None),
+            (Some(p), Some(r)) => (Some(p), Some(r)),
+        };
+
+        if let Some(button_id) = do_release {
+            tx.send(Message::ButtonRelease(button_id))?;
+        }
+
+        if let Some(button_id) = do_press {
+            tx.send(Message::ButtonPress(button_id))?;
+        }
+
+        *pressed = button_id;
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
 pub(crate) fn connect(
     i2c1: i2c::I2C1,
     sda: impl OutputPin + InputPin + 'static,
     scl: impl OutputPin + InputPin + 'static,
     buttons: [ButtonInfo; NUM_PER_PAGE],
     tx: messages::Sender,
-) {
+) -> Result<()> {
     let driver = i2c::I2cDriver::new(
         i2c1,
         sda,
         scl,
         &i2c::I2cConfig::new().baudrate(400.kHz().into()),
-    )
-    .unwrap();
+    )?;
 
     // let config = <i2c::config::MasterConfig as Default>::default().baudrate(400_u32.kHz().into());
     // let i2c1 =
     //     i2c::Master::<i2c::I2C1, _, _>::new(i2c1, i2c::MasterPins { sda, scl }, config).unwrap();
     let mut touch_screen = Ft6x36::new(driver, Dimension(320, 480));
-    touch_screen.init().unwrap();
-    match touch_screen.get_info() {
-        Some(info) => info!("Touch screen info: {info:?}"),
-        None => warn!("No info"),
-    }
-    let builder = thread::Builder::new().stack_size(8 * 1024);
-    builder
-        .spawn(move || {
-            let mut pressed: Option<ButtonId> = None;
-
-            loop {
-                // match touch_screen.get_diagnostics() {
-                //     Ok(diagnostics) => println!("Touch screen info: {diagnostics:?}"),
-                //     Err(err) => println!("No info: {err}"),
-                // }
-
-                let x = touch_screen.get_touch_event().unwrap();
-                // println!("get_touch_event: {x:?}");
-
-                let button_id = match x.p1 {
-                    Some(p1) => {
-                        let p1 = translate(p1);
-                        let button = get_button_for_point(&buttons, p1);
-                        match button {
-                            Some(button) => Some(button.id),
-                            None => Some(ButtonId::NotAButton),
-                        }
-                    }
-                    None => None,
-                };
-
-                let (do_release, do_press) = match (pressed, button_id) {
-                    (None, None) => (None, None),
-                    (None, Some(button_id)) => (None, Some(button_id)),
-                    (Some(button_id), None) => (Some(button_id), None),
-                    (Some(p), Some(r)) if p == r => (None, None),
-                    (Some(p), Some(r)) => (Some(p), Some(r)),
-                };
-
-                if let Some(button_id) = do_release {
-                    tx.send(Message::ButtonRelease(button_id)).unwrap();
-                }
+    // Kept across restarts, so a button held down when the touch screen
+    // failed still gets released.
+    let mut pressed: Option<ButtonId> = None;
 
-                if let Some(button_id) = do_press {
-                    tx.send(Message::ButtonPress(button_id)).unwrap();
-                }
-
-                pressed = button_id;
-                thread::sleep(Duration::from_millis(100));
-            }
-        })
-        .unwrap();
+    supervisor::spawn("touchscreen", Some(8 * 1024), tx.clone(), move || {
+        poll(&mut touch_screen, &buttons, &mut pressed, &tx)
+    })
 }
@@ -4,6 +4,8 @@ use anyhow::Result;
 
 use crate::display;
 use crate::messages;
+#[cfg(not(target_os = "espidf"))]
+use crate::mqtt;
 
 #[cfg(feature = "lca2021_badge")]
 pub mod lca2021_badge;
@@ -23,10 +25,22 @@ pub mod makerfab;
 #[cfg(feature = "makerfab")]
 use makerfab as board;
 
+#[cfg(feature = "simulator")]
+pub mod simulator;
+
+#[cfg(feature = "simulator")]
+use simulator as board;
+
 pub const NUM_CONTROLLERS_PER_PAGE: usize = board::NUM_CONTROLLERS_PER_PAGE;
 
 pub trait Board {
     fn get_display(&self) -> mpsc::Sender<display::DisplayCommand>;
+
+    /// WiFi signal strength in dBm, if connected.
+    fn get_rssi(&self) -> Option<i8>;
+
+    #[cfg(not(target_os = "espidf"))]
+    fn get_broker(&self) -> mqtt::memory::Broker;
 }
 
 pub fn configure_devices(tx: mpsc::Sender<messages::Message>) -> Result<impl Board> {
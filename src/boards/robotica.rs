@@ -27,6 +27,10 @@ impl Board for RoboticaBoard {
     fn get_display(&self) -> mpsc::Sender<display::DisplayCommand> {
         self.display.clone()
     }
+
+    fn get_rssi(&self) -> Option<i8> {
+        wifi::esp::get_rssi(&self.wifi)
+    }
 }
 
 pub fn configure_devices(tx: mpsc::Sender<messages::Message>) -> Result<RoboticaBoard> {
@@ -39,9 +43,9 @@ pub fn configure_devices(tx: mpsc::Sender<messages::Message>) -> Result<Robotica
 
     button::gpio::configure_button(pins.gpio15, tx.clone(), button::ButtonId::Physical(2))?;
 
-    button::gpio::configure_button(pins.gpio12, tx, button::ButtonId::Physical(3))?;
+    button::gpio::configure_button(pins.gpio12, tx.clone(), button::ButtonId::Physical(3))?;
 
-    let display = display::robotica::connect(13)?;
+    let display = display::robotica::connect(13, tx)?;
 
     let (wifi, sntp) = wifi::esp::connect(peripherals.modem)?;
 
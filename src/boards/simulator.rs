@@ -0,0 +1,164 @@
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use log::*;
+
+use crate::button::ButtonId;
+use crate::display;
+use crate::display::simulator::SharedScreen;
+use crate::messages;
+use crate::messages::Message;
+use crate::mqtt::memory::Broker;
+
+use super::Board;
+
+pub const NUM_CONTROLLERS_PER_PAGE: usize = display::simulator::NUM_PER_PAGE;
+
+pub struct Simulator {
+    display: mpsc::Sender<display::DisplayCommand>,
+    broker: Broker,
+}
+
+impl Board for Simulator {
+    fn get_display(&self) -> mpsc::Sender<display::DisplayCommand> {
+        self.display.clone()
+    }
+
+    fn get_rssi(&self) -> Option<i8> {
+        None
+    }
+
+    fn get_broker(&self) -> Broker {
+        self.broker.clone()
+    }
+}
+
+/// Reads button presses from the file named by `SIMULATOR_SCRIPT`, or from
+/// stdin if it is not set. One command per line:
+///
+/// * `press <button>`, `release <button>` or `click <button>`, where button
+///   is a physical button number, `c<n>` for controller `n`, `up`, `down` or
+///   `none`.
+/// * `sleep <ms>`, to give the other threads time to catch up.
+/// * `dump <file.png>`, to save the last flushed screen.
+/// * `mqtt <topic> <payload>` or `retain <topic> <payload>`, to publish to
+///   the in memory broker.
+/// * `disconnect` and `connect`, to break and restore the MQTT connection.
+/// * `refuse`, to break the MQTT connection as if the password was wrong.
+/// * `quit`.
+///
+/// Everything the remote publishes is logged.
+pub fn configure_devices(tx: mpsc::Sender<messages::Message>) -> Result<Simulator> {
+    let (display, screen) = display::simulator::connect(tx.clone())?;
+    let broker = Broker::new();
+
+    let published = broker.observe("#");
+    thread::Builder::new()
+        .name("broker".to_string())
+        .spawn(move || {
+            for (topic, data) in published {
+                info!(
+                    "Simulator broker got {topic}: {}",
+                    String::from_utf8_lossy(&data)
+                );
+            }
+        })?;
+
+    let input: Box<dyn BufRead + Send> = match env::var("SIMULATOR_SCRIPT") {
+        Ok(path) => Box::new(BufReader::new(File::open(path)?)),
+        Err(_) => Box::new(BufReader::new(io::stdin())),
+    };
+
+    let input_broker = broker.clone();
+    thread::Builder::new()
+        .name("simulator".to_string())
+        .spawn(move || {
+            for line in input.lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => {
+                        error!("Simulator failed to read input: {err}");
+                        break;
+                    }
+                };
+
+                if let Err(err) = do_command(&line, &tx, &screen, &input_broker) {
+                    error!("Simulator command {line:?} failed: {err}");
+                }
+            }
+            info!("Simulator input finished");
+        })?;
+
+    Ok(Simulator { display, broker })
+}
+
+fn do_command(
+    line: &str,
+    tx: &messages::Sender,
+    screen: &SharedScreen,
+    broker: &Broker,
+) -> Result<()> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(());
+    }
+
+    let (command, arg) = match line.split_once(char::is_whitespace) {
+        Some((command, arg)) => (command, arg.trim()),
+        None => (line, ""),
+    };
+
+    match command {
+        "press" => tx.send(Message::ButtonPress(parse_button(arg)?))?,
+        "release" => tx.send(Message::ButtonRelease(parse_button(arg)?))?,
+        "click" => {
+            let id = parse_button(arg)?;
+            tx.send(Message::ButtonPress(id))?;
+            tx.send(Message::ButtonRelease(id))?;
+        }
+        "sleep" => thread::sleep(Duration::from_millis(arg.parse()?)),
+        "dump" => {
+            screen.lock().unwrap().write_png(Path::new(arg))?;
+            info!("Simulator wrote {arg}");
+        }
+        "mqtt" | "retain" => {
+            let (topic, data) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+            broker.publish(topic, command == "retain", data.trim().as_bytes());
+        }
+        "disconnect" => broker.set_connected(false),
+        "connect" => broker.set_connected(true),
+        "refuse" => broker.refuse_auth(),
+        "quit" => std::process::exit(0),
+        _ => return Err(anyhow!("unknown command")),
+    }
+
+    Ok(())
+}
+
+fn parse_button(arg: &str) -> Result<ButtonId> {
+    let id = match arg {
+        "up" => ButtonId::PageUp,
+        "down" => ButtonId::PageDown,
+        "none" => ButtonId::NotAButton,
+        arg => match arg.strip_prefix('c') {
+            Some(id) => ButtonId::Controller(id.parse()?),
+            None => {
+                let id = arg.parse()?;
+                if id >= NUM_CONTROLLERS_PER_PAGE {
+                    return Err(anyhow!("no physical button {id}"));
+                }
+                ButtonId::Physical(id)
+            }
+        },
+    };
+    Ok(id)
+}
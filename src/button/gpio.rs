@@ -5,13 +5,14 @@ use std::sync::mpsc;
 use std::time::Duration;
 
 use anyhow::Result;
+use log::*;
 
 use embedded_hal::digital::ErrorType;
 use embedded_hal::digital::InputPin;
 use esp_idf_hal::gpio;
 use esp_idf_hal::gpio::PinDriver;
+use esp_idf_svc::timer::EspTimer;
 use esp_idf_svc::timer::EspTimerService;
-use std::thread;
 
 use crate::input::InputNotifyCallback;
 use crate::input::InputPinNotify;
@@ -19,6 +20,7 @@ use crate::input::Value;
 use crate::messages;
 use crate::messages::Message::ButtonPress;
 use crate::messages::Message::ButtonRelease;
+use crate::supervisor;
 
 use super::ButtonId;
 
@@ -49,10 +51,13 @@ pub fn button<T: InputPinNotify<Error = impl Debug + Display>>(
         };
 
         if has_changed {
-            if pressed {
-                tx.send(ButtonPress(id)).unwrap();
+            let message = if pressed {
+                ButtonPress(id)
             } else {
-                tx.send(ButtonRelease(id)).unwrap();
+                ButtonRelease(id)
+            };
+            if tx.send(message).is_err() {
+                error!("Main thread has gone, dropping button {id:?}");
             }
             *value.borrow_mut() = Some(v);
         }
@@ -71,87 +76,122 @@ pub struct Debouncer {
     tx: mpsc::Sender<DebouncerMessage>,
 }
 
+/// Kept across restarts of the debouncer thread.
+struct DebouncerState {
+    timer_set: bool,
+    value: Option<Value>,
+    subscriber: Option<InputNotifyCallback>,
+}
+
+fn debounce<T: gpio::InputPin>(
+    driver: &PinDriver<'_, T, gpio::Input>,
+    timer: &EspTimer,
+    debounce_time: Duration,
+    rx: &mpsc::Receiver<DebouncerMessage>,
+    state: &mut DebouncerState,
+) -> Result<()> {
+    let DebouncerState {
+        timer_set,
+        value,
+        subscriber,
+    } = state;
+
+    for msg in rx.iter() {
+        match msg {
+            DebouncerMessage::Input(new_value) => {
+                if !*timer_set {
+                    // println!("Got first value {new_value:?}");
+                    *value = Some(new_value);
+                    notify(subscriber, *value);
+                    timer.cancel()?;
+                    timer.after(debounce_time)?;
+                    *timer_set = true;
+                } else {
+                    // println!("Ignoring value {new_value:?}");
+                }
+            }
+            DebouncerMessage::Subscribe(new_subscriber) => {
+                // println!("Adding subscribe");
+                *subscriber = Some(new_subscriber);
+            }
+            DebouncerMessage::GetValue(reply_tx) => {
+                let out_value = if value.is_some() {
+                    *value
+                } else if driver.is_high() {
+                    Some(Value::High)
+                } else if driver.is_low() {
+                    Some(Value::Low)
+                } else {
+                    None
+                };
+                reply_tx.send(out_value)?;
+            }
+            DebouncerMessage::Timer => {
+                let raw_value = if driver.is_high() {
+                    Some(Value::High)
+                } else if driver.is_low() {
+                    Some(Value::Low)
+                } else {
+                    None
+                };
+                // println!("Got timer {value:?} {raw_value:?}");
+                if *value != raw_value {
+                    *value = raw_value;
+                    // println!("Sending {:?}", value);
+                    notify(subscriber, *value);
+                }
+                *timer_set = false;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 impl Debouncer {
-    pub fn new<T: gpio::InputPin + gpio::OutputPin>(pin: T, debounce_time_ms: u16) -> Self {
+    pub fn new<T: gpio::InputPin + gpio::OutputPin>(
+        pin: T,
+        debounce_time_ms: u16,
+        tx_to_client: messages::Sender,
+    ) -> Result<Self> {
         let (tx, rx) = mpsc::channel();
         let debounce_time = Duration::from_millis(debounce_time_ms as u64);
 
         let tx_clone = tx.clone();
-        let timer_service = EspTimerService::new().unwrap();
-        let timer = timer_service
-            .timer(move || {
-                tx_clone.send(DebouncerMessage::Timer).unwrap();
-            })
-            .unwrap();
+        let timer_service = EspTimerService::new()?;
+        let timer = timer_service.timer(move || {
+            if tx_clone.send(DebouncerMessage::Timer).is_err() {
+                error!("Debouncer has gone, dropping timer");
+            }
+        })?;
 
+        let mut driver = PinDriver::input(pin)?;
         let tx_clone = tx.clone();
-        thread::spawn(move || {
-            let mut driver = PinDriver::input(pin).unwrap();
-            driver.safe_subscribe(move |value| {
-                tx_clone.send(DebouncerMessage::Input(value)).unwrap();
-            });
-
-            let mut timer_set = false;
-            let mut value: Option<Value> = None;
-            let mut subscriber: Option<InputNotifyCallback> = None;
-
-            for msg in rx.iter() {
-                match msg {
-                    DebouncerMessage::Input(new_value) => {
-                        if !timer_set {
-                            // println!("Got first value {new_value:?}");
-                            value = Some(new_value);
-                            notify(&subscriber, value);
-                            timer.cancel().unwrap();
-                            timer.after(debounce_time).unwrap();
-                            timer_set = true;
-                        } else {
-                            // println!("Ignoring value {new_value:?}");
-                        }
-                    }
-                    DebouncerMessage::Subscribe(new_subscriber) => {
-                        // println!("Adding subscribe");
-                        subscriber = Some(new_subscriber);
-                    }
-                    DebouncerMessage::GetValue(reply_tx) => {
-                        let out_value = if value.is_some() {
-                            value
-                        } else if driver.is_high() {
-                            Some(Value::High)
-                        } else if driver.is_low() {
-                            Some(Value::Low)
-                        } else {
-                            None
-                        };
-                        reply_tx.send(out_value).unwrap();
-                    }
-                    DebouncerMessage::Timer => {
-                        let raw_value = if driver.is_high() {
-                            Some(Value::High)
-                        } else if driver.is_low() {
-                            Some(Value::Low)
-                        } else {
-                            None
-                        };
-                        // println!("Got timer {value:?} {raw_value:?}");
-                        if value != raw_value {
-                            value = raw_value;
-                            // println!("Sending {:?}", value);
-                            notify(&subscriber, value);
-                        }
-                        timer_set = false;
-                    }
-                }
+        driver.safe_subscribe(move |value| {
+            if tx_clone.send(DebouncerMessage::Input(value)).is_err() {
+                error!("Debouncer has gone, dropping input");
             }
         });
 
-        Debouncer { tx }
+        let mut state = DebouncerState {
+            timer_set: false,
+            value: None,
+            subscriber: None,
+        };
+
+        supervisor::spawn("debouncer", None, tx_to_client, move || {
+            // If we failed with the timer set, it may never go off.
+            state.timer_set = false;
+            debounce(&driver, &timer, debounce_time, &rx, &mut state)
+        })?;
+
+        Ok(Debouncer { tx })
     }
 
-    fn get_value(&self) -> Option<Value> {
+    fn get_value(&self) -> Result<Option<Value>> {
         let (tx, rx) = mpsc::channel();
-        self.tx.send(DebouncerMessage::GetValue(tx)).unwrap();
-        rx.recv().unwrap()
+        self.tx.send(DebouncerMessage::GetValue(tx))?;
+        Ok(rx.recv()?)
     }
 }
 
@@ -168,20 +208,21 @@ fn notify(subscriber: &Option<InputNotifyCallback>, new_state: Option<Value>) {
 
 impl InputPinNotify for Debouncer {
     fn safe_subscribe<F: Fn(Value) + Send + 'static>(&mut self, callback: F) {
-        self.tx
-            .send(DebouncerMessage::Subscribe(Box::new(callback)))
-            .unwrap();
+        let message = DebouncerMessage::Subscribe(Box::new(callback));
+        if self.tx.send(message).is_err() {
+            error!("Debouncer has gone, could not subscribe");
+        }
     }
 }
 
 impl InputPin for Debouncer {
     fn is_high(&self) -> Result<bool, Self::Error> {
-        let value = self.get_value();
+        let value = self.get_value()?;
         Ok(matches!(value, Some(Value::High)))
     }
 
     fn is_low(&self) -> Result<bool, Self::Error> {
-        let value = self.get_value();
+        let value = self.get_value()?;
         Ok(matches!(value, Some(Value::Low)))
     }
 }
@@ -197,7 +238,7 @@ pub fn configure_button(
 ) -> Result<()> {
     // let mut pin = gpio::PinDriver::input(pin)?;
     // pin.set_pull(Pull::Up)?;
-    let debounced_encoder_pin = Debouncer::new(pin, 200);
+    let debounced_encoder_pin = Debouncer::new(pin, 200, tx.clone())?;
     button(debounced_encoder_pin, Active::Low, id, tx);
     Ok(())
 }
@@ -4,16 +4,18 @@ use std::sync::mpsc;
 use std::time::Duration;
 
 use anyhow::Result;
+use log::*;
 
 use embedded_hal::digital::ErrorType;
 use embedded_hal::digital::InputPin;
+use esp_idf_svc::timer::EspTimer;
 use esp_idf_svc::timer::EspTimerService;
-use std::thread;
 
 use crate::input::InputNotifyCallback;
 use crate::input::InputPinNotify;
 use crate::input::Value;
 use crate::messages;
+use crate::supervisor;
 
 use super::gpio::button;
 use super::gpio::Active;
@@ -36,127 +38,163 @@ enum TouchDebouncerState {
     ActivePoll,
 }
 
+/// Kept across restarts of the debouncer thread.
+struct State {
+    state: TouchDebouncerState,
+    value: Option<Value>,
+    raw_value: Option<Value>,
+    subscriber: Option<InputNotifyCallback>,
+}
+
+fn debounce<T: InputPinNotify<Error = impl Debug + Display>>(
+    pin: &T,
+    timer: &EspTimer,
+    debounce_time: Duration,
+    poll_time: Duration,
+    rx: &mpsc::Receiver<TouchDebouncerMessage>,
+    state: &mut State,
+) -> Result<()> {
+    let State {
+        state,
+        value,
+        raw_value,
+        subscriber,
+    } = state;
+
+    for msg in rx.iter() {
+        match msg {
+            TouchDebouncerMessage::Input(_) => {
+                if pin.is_low().unwrap_or(false) {
+                    if let TouchDebouncerState::Inactive = state {
+                        // println!("Processing value low value");
+                        *value = Some(Value::Low);
+                        notify(subscriber, *value);
+                        timer.cancel()?;
+                        timer.after(debounce_time)?;
+                        *state = TouchDebouncerState::Debounce;
+                    } else {
+                        // println!("Ignoring value {:?}", value);
+                    }
+                    *raw_value = Some(Value::Low);
+                } else {
+                    // println!("got event for high value");
+                }
+            }
+            TouchDebouncerMessage::Subscribe(new_subscriber) => {
+                // println!("Adding subscribe");
+                *subscriber = Some(new_subscriber);
+            }
+            TouchDebouncerMessage::GetValue(reply_tx) => {
+                let out_value = if value.is_some() {
+                    *value
+                } else if pin.is_high().unwrap_or(false) {
+                    Some(Value::High)
+                } else if pin.is_low().unwrap_or(false) {
+                    Some(Value::Low)
+                } else {
+                    None
+                };
+                reply_tx.send(out_value)?;
+            }
+            TouchDebouncerMessage::Timer => {
+                if let TouchDebouncerState::Debounce = state {
+                    println!("Got debounce timer");
+                    if value != raw_value {
+                        *value = *raw_value;
+                        // println!("Sending {:?}", value);
+                        notify(subscriber, *value);
+                    }
+                    *state = TouchDebouncerState::ActivePoll;
+                }
+
+                if let TouchDebouncerState::ActivePoll = state {
+                    // println!("Got poll timer");
+                    if pin.is_high().unwrap_or(false) {
+                        // println!(".... is high");
+                        *state = TouchDebouncerState::Inactive;
+                        *value = Some(Value::High);
+                        notify(subscriber, *value);
+                    }
+                }
+
+                if let TouchDebouncerState::ActivePoll = state {
+                    // println!("resetting poll timer");
+                    timer.after(poll_time)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
 impl TouchDebouncer {
     pub fn new<T: InputPinNotify<Error = impl Debug + Display> + Send + 'static>(
         mut pin: T,
         debounce_time_ms: u16,
         poll_time_ms: u16,
-    ) -> Self {
+        tx_to_client: messages::Sender,
+    ) -> Result<Self> {
         let (tx, rx) = mpsc::channel();
         let debounce_time = Duration::from_millis(debounce_time_ms as u64);
         let poll_time = Duration::from_millis(poll_time_ms as u64);
 
         let tx_clone = tx.clone();
         pin.safe_subscribe(move |value| {
-            tx_clone.send(TouchDebouncerMessage::Input(value)).unwrap();
+            if tx_clone.send(TouchDebouncerMessage::Input(value)).is_err() {
+                error!("Touch debouncer has gone, dropping input");
+            }
         });
 
-        let timer_service = EspTimerService::new().unwrap();
+        let timer_service = EspTimerService::new()?;
 
         let tx_clone = tx.clone();
-        let timer = timer_service
-            .timer(move || {
-                tx_clone.send(TouchDebouncerMessage::Timer).unwrap();
-            })
-            .unwrap();
-
-        thread::spawn(move || {
-            let mut state = TouchDebouncerState::Inactive;
-            let mut value: Option<Value> = None;
-            let mut raw_value: Option<Value> = value;
-            let mut subscriber: Option<InputNotifyCallback> = None;
-
-            for msg in rx.iter() {
-                match msg {
-                    TouchDebouncerMessage::Input(_) => {
-                        if pin.is_low().unwrap_or(false) {
-                            if let TouchDebouncerState::Inactive = state {
-                                // println!("Processing value low value");
-                                value = Some(Value::Low);
-                                notify(&subscriber, value);
-                                timer.cancel().unwrap();
-                                timer.after(debounce_time).unwrap();
-                                state = TouchDebouncerState::Debounce;
-                            } else {
-                                // println!("Ignoring value {:?}", value);
-                            }
-                            raw_value = Some(Value::Low);
-                        } else {
-                            // println!("got event for high value");
-                        }
-                    }
-                    TouchDebouncerMessage::Subscribe(new_subscriber) => {
-                        // println!("Adding subscribe");
-                        subscriber = Some(new_subscriber);
-                    }
-                    TouchDebouncerMessage::GetValue(reply_tx) => {
-                        let out_value = if value.is_some() {
-                            value
-                        } else if pin.is_high().unwrap_or(false) {
-                            Some(Value::High)
-                        } else if pin.is_low().unwrap_or(false) {
-                            Some(Value::Low)
-                        } else {
-                            None
-                        };
-                        reply_tx.send(out_value).unwrap();
-                    }
-                    TouchDebouncerMessage::Timer => {
-                        if let TouchDebouncerState::Debounce = state {
-                            println!("Got debounce timer");
-                            if value != raw_value {
-                                value = raw_value;
-                                // println!("Sending {:?}", value);
-                                notify(&subscriber, value);
-                            }
-                            state = TouchDebouncerState::ActivePoll;
-                        }
-
-                        if let TouchDebouncerState::ActivePoll = state {
-                            // println!("Got poll timer");
-                            if pin.is_high().unwrap_or(false) {
-                                // println!(".... is high");
-                                state = TouchDebouncerState::Inactive;
-                                value = Some(Value::High);
-                                notify(&subscriber, value);
-                            }
-                        }
-
-                        if let TouchDebouncerState::ActivePoll = state {
-                            // println!("resetting poll timer");
-                            timer.after(poll_time).unwrap();
-                        }
-                    }
-                }
+        let timer = timer_service.timer(move || {
+            if tx_clone.send(TouchDebouncerMessage::Timer).is_err() {
+                error!("Touch debouncer has gone, dropping timer");
             }
-        });
-
-        TouchDebouncer { tx }
+        })?;
+
+        let mut state = State {
+            state: TouchDebouncerState::Inactive,
+            value: None,
+            raw_value: None,
+            subscriber: None,
+        };
+
+        supervisor::spawn("touch", None, tx_to_client, move || {
+            // If we failed while waiting for the timer, it may never go off.
+            state.state = TouchDebouncerState::Inactive;
+            debounce(&pin, &timer, debounce_time, poll_time, &rx, &mut state)
+        })?;
+
+        Ok(TouchDebouncer { tx })
     }
 
-    fn get_value(&self) -> Option<Value> {
+    fn get_value(&self) -> Result<Option<Value>> {
         let (tx, rx) = mpsc::channel();
-        self.tx.send(TouchDebouncerMessage::GetValue(tx)).unwrap();
-        rx.recv().unwrap()
+        self.tx.send(TouchDebouncerMessage::GetValue(tx))?;
+        Ok(rx.recv()?)
     }
 }
 
 impl InputPinNotify for TouchDebouncer {
     fn safe_subscribe<F: Fn(Value) + Send + 'static>(&mut self, callback: F) {
-        self.tx
-            .send(TouchDebouncerMessage::Subscribe(Box::new(callback)))
-            .unwrap();
+        let message = TouchDebouncerMessage::Subscribe(Box::new(callback));
+        if self.tx.send(message).is_err() {
+            error!("Touch debouncer has gone, could not subscribe");
+        }
     }
 }
 
 impl InputPin for TouchDebouncer {
     fn is_high(&self) -> Result<bool, Self::Error> {
-        let value = self.get_value();
+        let value = self.get_value()?;
         Ok(matches!(value, Some(Value::High)))
     }
 
     fn is_low(&self) -> Result<bool, Self::Error> {
-        let value = self.get_value();
+        let value = self.get_value()?;
         Ok(matches!(value, Some(Value::Low)))
     }
 }
@@ -170,7 +208,7 @@ pub fn configure_touch_button<T: 'static + InputPinNotify<Error = impl Debug + D
     tx: messages::Sender,
     id: ButtonId,
 ) -> Result<()> {
-    let debounced_encoder_pin = TouchDebouncer::new(pin, 30, 100);
+    let debounced_encoder_pin = TouchDebouncer::new(pin, 30, 100, tx.clone())?;
     button(debounced_encoder_pin, Active::Low, id, tx);
     Ok(())
 }
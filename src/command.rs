@@ -0,0 +1,28 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Sent to the per-device command topic to poke a remote from afar.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum RemoteCommand {
+    /// Press and release a controller, as numbered across all pages.
+    Press {
+        controller: usize,
+    },
+    ShowPage {
+        page: usize,
+    },
+    /// Turn the display off until something would turn it on again.
+    Blank,
+    /// Turn the display on, as if a button was pressed.
+    UnBlank,
+    Restart,
+}
+
+/// Published in reply to every command.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum CommandReply {
+    Ok,
+    Error { reason: String },
+}
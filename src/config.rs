@@ -1,4 +1,13 @@
+use anyhow::bail;
+use anyhow::Result;
+use log::*;
+use serde::Deserialize;
+use serde::Serialize;
+
 use crate::display::icon::Icon;
+use crate::hardware::Storage;
+use crate::mqtt::Decoding;
+use crate::mqtt::Payload;
 use robotica_common::controllers::lights;
 use robotica_common::controllers::music;
 use robotica_common::controllers::switch;
@@ -8,13 +17,69 @@ use robotica_common::controllers::ControllerTrait;
 use robotica_common::controllers::DisplayState;
 use robotica_common::controllers::Subscription;
 
-pub const NUM_CONTROLLERS: usize = 6;
-pub const NIGHT_TOPIC: &str = "state/Brian/Night/power";
-pub const LIGHT_TOPIC: &str = "state/Brian/Light/power";
+const CONFIG_KEY: &str = "config";
+
+pub fn get_config_topic(unique_id: &str) -> String {
+    format!("config/robotica-remote/{unique_id}")
+}
+
+pub fn get_config_ack_topic(unique_id: &str) -> String {
+    format!("config/robotica-remote/{unique_id}/ack")
+}
+
+pub fn get_command_topic(unique_id: &str) -> String {
+    format!("command/robotica-remote/{unique_id}")
+}
+
+pub fn get_command_reply_topic(unique_id: &str) -> String {
+    format!("command/robotica-remote/{unique_id}/reply")
+}
+
+/// Retained `online` while we are connected, `offline` otherwise.
+pub fn get_availability_topic(unique_id: &str) -> String {
+    format!("state/robotica-remote/{unique_id}/availability")
+}
+
+pub fn get_device_info_topic(unique_id: &str) -> String {
+    format!("state/robotica-remote/{unique_id}/info")
+}
+
+pub fn get_telemetry_topic(unique_id: &str) -> String {
+    format!("state/robotica-remote/{unique_id}/telemetry")
+}
+
+/// Failures of worker threads, not retained.
+pub fn get_error_topic(unique_id: &str) -> String {
+    format!("state/robotica-remote/{unique_id}/error")
+}
+
+/// Retained title of the page being shown.
+pub fn get_page_topic(unique_id: &str) -> String {
+    format!("state/robotica-remote/{unique_id}/page")
+}
+
+/// Retained `ON` or `OFF`.
+pub fn get_display_topic(unique_id: &str) -> String {
+    format!("state/robotica-remote/{unique_id}/display")
+}
+
+/// `press` every time the physical button is pressed.
+pub fn get_button_topic(unique_id: &str, id: usize) -> String {
+    format!("state/robotica-remote/{unique_id}/button/{id}")
+}
+
+/// Published in reply to every configuration received over MQTT.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ConfigAck {
+    Accepted,
+    Rejected { reason: String },
+}
 
 pub struct Controller {
     name: String,
     icon: Icon,
+    offline: OfflinePolicy,
     controller: Box<dyn ControllerTrait>,
 }
 
@@ -44,84 +109,529 @@ impl Controller {
     }
 
     pub fn get_icon(&self) -> Icon {
-        self.icon
+        self.icon.clone()
+    }
+
+    pub fn get_offline_policy(&self) -> OfflinePolicy {
+        self.offline
     }
 }
 
-pub fn get_controllers_config() -> [Controller; NUM_CONTROLLERS] {
-    [
-        Controller {
-            name: "On".to_string(),
-            icon: Icon::Light,
-            controller: Box::new(
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionConfig {
+    TurnOn,
+    TurnOff,
+    Toggle,
+}
+
+impl From<ActionConfig> for Action {
+    fn from(action: ActionConfig) -> Self {
+        match action {
+            ActionConfig::TurnOn => Action::TurnOn,
+            ActionConfig::TurnOff => Action::TurnOff,
+            ActionConfig::Toggle => Action::Toggle,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControllerKind {
+    Light {
+        topic_substr: String,
+        action: ActionConfig,
+        scene: String,
+        priority: usize,
+    },
+    Switch {
+        topic_substr: String,
+        action: ActionConfig,
+    },
+    Music {
+        topic_substr: String,
+        action: ActionConfig,
+        play_list: String,
+    },
+}
+
+/// What to do with a press made while MQTT is disconnected.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum OfflinePolicy {
+    Replay,
+    ReplayIfYounger {
+        max_age_secs: u64,
+    },
+    #[default]
+    Drop,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ControllerConfig {
+    pub name: String,
+    pub icon: Icon,
+    #[serde(default)]
+    pub offline: OfflinePolicy,
+    #[serde(flatten)]
+    pub kind: ControllerKind,
+}
+
+impl ControllerConfig {
+    pub fn create_controller(&self) -> Controller {
+        let controller: Box<dyn ControllerTrait> = match &self.kind {
+            ControllerKind::Light {
+                topic_substr,
+                action,
+                scene,
+                priority,
+            } => Box::new(
                 lights::Config {
-                    topic_substr: "Brian/Light".to_string(),
-                    action: Action::Toggle,
-                    scene: "default".to_string(),
-                    priority: 100,
+                    topic_substr: topic_substr.clone(),
+                    action: (*action).into(),
+                    scene: scene.clone(),
+                    priority: *priority,
                 }
                 .create_controller(),
             ),
-        },
-        Controller {
-            name: "Auto".to_string(),
-            icon: Icon::Light,
-            controller: Box::new(
-                lights::Config {
-                    topic_substr: "Brian/Light".to_string(),
-                    action: Action::Toggle,
-                    scene: "auto".to_string(),
-                    priority: 100,
+            ControllerKind::Switch {
+                topic_substr,
+                action,
+            } => Box::new(
+                switch::Config {
+                    topic_substr: topic_substr.clone(),
+                    action: (*action).into(),
                 }
                 .create_controller(),
             ),
-        },
-        Controller {
-            name: "Brian Fan".to_string(),
-            icon: Icon::Fan,
-            controller: Box::new(
-                switch::Config {
-                    topic_substr: "Brian/Fan".to_string(),
-                    action: Action::Toggle,
+            ControllerKind::Music {
+                topic_substr,
+                action,
+                play_list,
+            } => Box::new(
+                music::Config {
+                    topic_substr: topic_substr.clone(),
+                    action: (*action).into(),
+                    play_list: play_list.clone(),
                 }
                 .create_controller(),
             ),
-        },
+        };
+
         Controller {
+            name: self.name.clone(),
+            icon: self.icon.clone(),
+            offline: self.offline,
+            controller,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct PageConfig {
+    pub title: String,
+    pub slots: Vec<Option<ControllerConfig>>,
+}
+
+/// The display is kept on while the last payload seen on `topic` is
+/// `payload`.
+///
+/// With JSON decoding, `payload` is parsed as JSON too, so formatting and
+/// the order of keys don't matter.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct BlankCondition {
+    pub topic: String,
+    pub payload: String,
+    #[serde(default)]
+    pub decoding: Decoding,
+    /// Whether the condition is met before anything is received on `topic`.
+    #[serde(default)]
+    pub initially_met: bool,
+}
+
+impl BlankCondition {
+    pub fn matches(&self, payload: &Payload) -> bool {
+        match payload {
+            Payload::Text(text) => *text == self.payload,
+            Payload::Json(value) => serde_json::from_str::<serde_json::Value>(&self.payload)
+                .map_or(false, |expected| expected == *value),
+            Payload::Raw(data) => data == self.payload.as_bytes(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Combine {
+    Any,
+    All,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PressWhileBlank {
+    WakeOnly,
+    WakeAndAct,
+}
+
+/// When to turn the display off.
+///
+/// The display is on while the conditions, combined with `combine`, are met,
+/// or for `timeout_secs` after the last press. A condition counts as met
+/// until something has been received on its topic, so the display stays on
+/// if MQTT is unavailable.
+///
+/// Brightnesses are percentages. When the display should be off it is dimmed
+/// to `dim_brightness` instead, unless that is 0.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BlankingConfig {
+    pub conditions: Vec<BlankCondition>,
+    pub combine: Combine,
+    pub timeout_secs: u64,
+    pub press_while_blank: PressWhileBlank,
+    pub brightness: u8,
+    pub dim_brightness: u8,
+}
+
+impl Default for BlankingConfig {
+    fn default() -> Self {
+        BlankingConfig {
+            conditions: vec![],
+            combine: Combine::Any,
+            timeout_secs: 10,
+            press_while_blank: PressWhileBlank::WakeOnly,
+            brightness: 100,
+            dim_brightness: 0,
+        }
+    }
+}
+
+/// Settings for the MQTT connection, which only take effect on restart.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    /// Messages bigger than this, in bytes, are dropped.
+    pub max_message_size: usize,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            max_message_size: 16 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    /// How often to publish telemetry, or 0 to never.
+    pub interval_secs: u64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig { interval_secs: 60 }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ClockConfig {
+    /// POSIX TZ string for the time shown on the status bar.
+    pub timezone: String,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            timezone: "UTC0".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThemeName {
+    #[default]
+    Default,
+    Night,
+    HighContrast,
+}
+
+/// How the displays draw things, sent to the display thread as is.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DisplayConfig {
+    /// Scroll the name of a pressed controller if it does not fit on one line.
+    pub marquee: bool,
+    pub theme: ThemeName,
+    /// Show a dim clock that moves around rather than turning off.
+    pub screensaver: bool,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Config {
+    pub pages: Vec<PageConfig>,
+    #[serde(default)]
+    pub blanking: BlankingConfig,
+    #[serde(default)]
+    pub mqtt: MqttConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub clock: ClockConfig,
+    #[serde(default)]
+    pub display: DisplayConfig,
+}
+
+/// A page of controllers, referring to each by its index in the list of all
+/// controllers.
+pub struct Page {
+    pub title: String,
+    pub slots: Vec<Option<usize>>,
+}
+
+impl Config {
+    pub fn create_controllers(&self) -> (Vec<Controller>, Vec<Page>) {
+        let mut controllers = Vec::new();
+        let mut pages = Vec::with_capacity(self.pages.len());
+
+        for page in &self.pages {
+            let slots = page
+                .slots
+                .iter()
+                .map(|slot| {
+                    slot.as_ref().map(|c| {
+                        controllers.push(c.create_controller());
+                        controllers.len() - 1
+                    })
+                })
+                .collect();
+
+            pages.push(Page {
+                title: page.title.clone(),
+                slots,
+            });
+        }
+
+        (controllers, pages)
+    }
+
+    /// The controllers that have always been built in, filling each page in
+    /// turn, for when nothing has been configured.
+    pub fn default_for(num_per_page: usize) -> Self {
+        let controllers = default_controllers();
+        let pages = controllers
+            .chunks(num_per_page.max(1))
+            .enumerate()
+            .map(|(index, chunk)| PageConfig {
+                title: format!("Page {}", index + 1),
+                slots: chunk.iter().cloned().map(Some).collect(),
+            })
+            .collect();
+
+        Config {
+            pages,
+            // Stay on during the day, or at night while the light is on.
+            blanking: BlankingConfig {
+                conditions: vec![
+                    BlankCondition {
+                        topic: "state/Brian/Night/power".to_string(),
+                        payload: "OFF".to_string(),
+                        decoding: Decoding::Text,
+                        initially_met: true,
+                    },
+                    BlankCondition {
+                        topic: "state/Brian/Light/power".to_string(),
+                        payload: "ON".to_string(),
+                        decoding: Decoding::Text,
+                        initially_met: false,
+                    },
+                ],
+                ..Default::default()
+            },
+            mqtt: MqttConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            clock: ClockConfig::default(),
+            display: DisplayConfig::default(),
+        }
+    }
+}
+
+fn default_controllers() -> Vec<ControllerConfig> {
+    vec![
+        ControllerConfig {
+            name: "On".to_string(),
+            icon: Icon::new("light"),
+            offline: OfflinePolicy::Drop,
+            kind: ControllerKind::Light {
+                topic_substr: "Brian/Light".to_string(),
+                action: ActionConfig::Toggle,
+                scene: "default".to_string(),
+                priority: 100,
+            },
+        },
+        ControllerConfig {
+            name: "Auto".to_string(),
+            icon: Icon::new("light"),
+            offline: OfflinePolicy::Drop,
+            kind: ControllerKind::Light {
+                topic_substr: "Brian/Light".to_string(),
+                action: ActionConfig::Toggle,
+                scene: "auto".to_string(),
+                priority: 100,
+            },
+        },
+        ControllerConfig {
+            name: "Brian Fan".to_string(),
+            icon: Icon::new("fan"),
+            offline: OfflinePolicy::Drop,
+            kind: ControllerKind::Switch {
+                topic_substr: "Brian/Fan".to_string(),
+                action: ActionConfig::Toggle,
+            },
+        },
+        ControllerConfig {
             name: "Passage".to_string(),
-            icon: Icon::Light,
-            controller: Box::new(
-                lights::Config {
-                    topic_substr: "Passage/Light".to_string(),
-                    action: Action::Toggle,
-                    scene: "default".to_string(),
-                    priority: 100,
-                }
-                .create_controller(),
-            ),
+            icon: Icon::new("light"),
+            offline: OfflinePolicy::Drop,
+            kind: ControllerKind::Light {
+                topic_substr: "Passage/Light".to_string(),
+                action: ActionConfig::Toggle,
+                scene: "default".to_string(),
+                priority: 100,
+            },
         },
-        Controller {
+        ControllerConfig {
             name: "Brian Wake-Up".to_string(),
-            icon: Icon::Speaker,
-            controller: Box::new(
-                music::Config {
-                    topic_substr: "Brian/Robotica".to_string(),
-                    action: Action::Toggle,
-                    play_list: "wake_up".to_string(),
-                }
-                .create_controller(),
-            ),
+            icon: Icon::new("speaker"),
+            offline: OfflinePolicy::Drop,
+            kind: ControllerKind::Music {
+                topic_substr: "Brian/Robotica".to_string(),
+                action: ActionConfig::Toggle,
+                play_list: "wake_up".to_string(),
+            },
         },
-        Controller {
+        ControllerConfig {
             name: "TV".to_string(),
-            icon: Icon::TV,
-            controller: Box::new(
-                switch::Config {
-                    topic_substr: "Dining/TvSwitch".to_string(),
-                    action: Action::Toggle,
-                }
-                .create_controller(),
-            ),
+            icon: Icon::new("tv"),
+            offline: OfflinePolicy::Drop,
+            kind: ControllerKind::Switch {
+                topic_substr: "Dining/TvSwitch".to_string(),
+                action: ActionConfig::Toggle,
+            },
         },
     ]
 }
+
+pub fn parse_config(data: &[u8], num_per_page: usize) -> Result<Config> {
+    let config: Config = serde_json::from_slice(data)?;
+    if config.pages.is_empty() {
+        bail!("No pages defined");
+    }
+    for page in &config.pages {
+        if page.slots.len() > num_per_page {
+            bail!(
+                "Page {:?} has {} slots but only {} fit",
+                page.title,
+                page.slots.len(),
+                num_per_page
+            );
+        }
+    }
+    Ok(config)
+}
+
+pub fn save_config(storage: &mut Storage, data: &str) -> Result<()> {
+    storage.store(CONFIG_KEY, data.as_bytes())
+}
+
+/// Load the configuration saved in storage.
+///
+/// If there is no saved configuration, or it can't be used, the default is
+/// returned along with the reason the saved one was rejected.
+pub fn load_config(storage: &Storage, num_per_page: usize) -> (Config, Option<String>) {
+    let data = match storage.load(CONFIG_KEY) {
+        Ok(Some(data)) => data,
+        Ok(None) => {
+            info!("No saved config, using default");
+            return (Config::default_for(num_per_page), None);
+        }
+        Err(err) => {
+            error!("Could not load config: {err}");
+            return (
+                Config::default_for(num_per_page),
+                Some(format!("Load config: {err}")),
+            );
+        }
+    };
+
+    match parse_config(&data, num_per_page) {
+        Ok(config) => (config, None),
+        Err(err) => {
+            error!("Could not parse config: {err}");
+            (
+                Config::default_for(num_per_page),
+                Some(format!("Bad config: {err}")),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn condition(payload: &str, decoding: Decoding) -> BlankCondition {
+        BlankCondition {
+            topic: "state/Brian/Light/power".to_string(),
+            payload: payload.to_string(),
+            decoding,
+            initially_met: false,
+        }
+    }
+
+    #[test]
+    fn test_blank_condition_matches() {
+        let text = |text: &str| Payload::Text(text.to_string());
+        let json = |json: &str| Payload::Json(serde_json::from_str(json).unwrap());
+        let raw = |data: &[u8]| Payload::Raw(data.to_vec());
+
+        let cases = [
+            ("ON", Decoding::Text, text("ON"), true),
+            ("ON", Decoding::Text, text("OFF"), false),
+            ("ON", Decoding::Text, text("on"), false),
+            ("ON", Decoding::Text, text("ON "), false),
+            // Compared as values, so formatting does not matter.
+            (
+                r#"{"on": true}"#,
+                Decoding::Json,
+                json(r#"{ "on":true }"#),
+                true,
+            ),
+            (
+                r#"{"on": true}"#,
+                Decoding::Json,
+                json(r#"{"on": false}"#),
+                false,
+            ),
+            ("1", Decoding::Json, json("1.0"), false),
+            ("\"ON\"", Decoding::Json, json("\"ON\""), true),
+            // A payload in the config that is not JSON never matches.
+            ("ON", Decoding::Json, json("\"ON\""), false),
+            ("ON", Decoding::Raw, raw(b"ON"), true),
+            ("ON", Decoding::Raw, raw(b"ON\0"), false),
+            ("", Decoding::Raw, raw(b""), true),
+        ];
+        for (expected, decoding, payload, matches) in cases {
+            let condition = condition(expected, decoding);
+            assert_eq!(
+                condition.matches(&payload),
+                matches,
+                "{expected} against {payload:?}"
+            );
+        }
+    }
+}
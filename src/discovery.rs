@@ -0,0 +1,68 @@
+use serde_json::json;
+
+use crate::config;
+
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Home Assistant MQTT discovery documents for this remote, as topic and
+/// payload pairs to publish retained.
+///
+/// The remote shows up as a device with a sensor for the current page, a
+/// binary sensor for whether the display is on, and a trigger for each
+/// button.
+pub fn get_documents(unique_id: &str, num_buttons: usize) -> Vec<(String, String)> {
+    let node_id = format!("robotica_remote_{unique_id}");
+    let availability_topic = config::get_availability_topic(unique_id);
+    let device = json!({
+        "identifiers": [node_id],
+        "name": format!("Robotica Remote {unique_id}"),
+        "manufacturer": "Robotica",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+
+    let mut documents = vec![
+        (
+            format!("{DISCOVERY_PREFIX}/sensor/{node_id}/page/config"),
+            json!({
+                "name": "Page",
+                "unique_id": format!("{node_id}_page"),
+                "state_topic": config::get_page_topic(unique_id),
+                "availability_topic": availability_topic,
+                "icon": "mdi:book-open-page-variant",
+                "device": device,
+            }),
+        ),
+        (
+            format!("{DISCOVERY_PREFIX}/binary_sensor/{node_id}/display/config"),
+            json!({
+                "name": "Display",
+                "unique_id": format!("{node_id}_display"),
+                "state_topic": config::get_display_topic(unique_id),
+                "availability_topic": availability_topic,
+                "payload_on": "ON",
+                "payload_off": "OFF",
+                "icon": "mdi:monitor",
+                "device": device,
+            }),
+        ),
+    ];
+
+    for id in 0..num_buttons {
+        documents.push((
+            format!("{DISCOVERY_PREFIX}/device_automation/{node_id}/button_{id}/config"),
+            json!({
+                "automation_type": "trigger",
+                "topic": config::get_button_topic(unique_id, id),
+                "type": "button_short_press",
+                "subtype": format!("button_{}", id + 1),
+                "payload": "press",
+                "device": device,
+            }),
+        ));
+    }
+
+    documents
+        .into_iter()
+        .map(|(topic, document)| (topic, document.to_string()))
+        .collect()
+}
@@ -1,29 +1,57 @@
 use std::cmp::max;
+use std::cmp::min;
 use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+use std::time::Instant;
 
+use anyhow::anyhow;
+use anyhow::Result;
 use embedded_graphics_framebuf::FrameBuf;
 use log::*;
 
-use embedded_graphics::mono_font::{ascii::FONT_10X20, MonoTextStyle};
+use embedded_graphics::mono_font::{ascii::FONT_10X20, ascii::FONT_6X10, MonoTextStyle};
 use embedded_graphics::{
     draw_target::DrawTarget,
+    geometry::Dimensions,
     image::Image,
     mono_font::ascii::FONT_5X8,
     pixelcolor::{Gray8, Rgb555, Rgb888},
-    prelude::{ImageDrawable, PixelColor, Point, Primitive, RgbColor, Size},
-    primitives::{PrimitiveStyleBuilder, Rectangle},
-    text::{Alignment, Text},
+    prelude::{ImageDrawable, Pixel, PixelColor, Point, Primitive, RgbColor, Size},
+    primitives::{Circle, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
+    text::{Alignment, Baseline, Text},
     Drawable,
 };
 use robotica_common::controllers::DisplayState;
 use tinytga::DynamicTga;
 
 use super::icon::Icon;
+use super::label;
+use super::theme::Theme;
 use super::DisplayCommand;
+use super::Status;
+use crate::config::DisplayConfig;
+use crate::config::ThemeName;
+use crate::hardware;
+use crate::mqtt::ConnectionState;
+use robotica_remote::pack::Pack;
+
+/// How often a scrolling name moves along by one character.
+const MARQUEE_STEP: Duration = Duration::from_millis(300);
+
+/// Brightness of the screensaver clock, as a percentage.
+const SCREENSAVER_BRIGHTNESS: u8 = 10;
 
 pub trait FlushableDrawTarget: DrawTarget {
     fn flush(&mut self) -> Result<(), Self::Error>;
     fn set_display_on(&mut self, on: bool) -> Result<(), Self::Error>;
+    /// Brightness while on, as a percentage.
+    fn set_brightness(&mut self, level: u8) -> Result<(), Self::Error>;
+
+    /// True for displays that can only turn pixels on or off.
+    fn is_monochrome(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Clone)]
@@ -32,40 +60,241 @@ pub struct State {
     icon: Icon,
     name: String,
     pressed: bool,
+    queued: bool,
+    /// How far the name has scrolled, while it is scrolling.
+    scroll: Option<usize>,
+}
+
+/// What the displays should be showing, kept across restarts of the display
+/// thread so it can put it all back.
+pub struct Contents {
+    icons: Option<Pack<'static>>,
+    settings: DisplayConfig,
+    theme: Theme,
+    started: bool,
+    blank: bool,
+    brightness: u8,
+    /// How many times the screensaver clock has moved.
+    screensaver_step: u32,
+    states: Vec<Option<State>>,
+    title: Option<String>,
+    page: Option<(usize, usize)>,
+    connection: ConnectionState,
+    status: Status,
+}
+
+impl Contents {
+    pub fn new() -> Self {
+        Contents {
+            icons: load_icons(),
+            settings: DisplayConfig::default(),
+            theme: Theme::new(ThemeName::Default),
+            started: false,
+            blank: false,
+            brightness: 100,
+            screensaver_step: 0,
+            states: Vec::new(),
+            title: None,
+            page: None,
+            connection: ConnectionState::Connecting,
+            status: Status::default(),
+        }
+    }
+}
+
+/// The icon pack in flash, or None to draw without icons if there isn't a
+/// valid one.
+fn load_icons() -> Option<Pack<'static>> {
+    match hardware::map_assets().and_then(Pack::parse) {
+        Ok(icons) => {
+            info!("Icon pack has {} icons", icons.names().count());
+            Some(icons)
+        }
+        Err(err) => {
+            error!("Could not load icon pack: {err:?}");
+            None
+        }
+    }
+}
+
+/// The theme named in the settings, made to suit the displays.
+fn get_theme<D: FlushableDrawTarget>(settings: &DisplayConfig, displays: &[D]) -> Theme {
+    let theme = Theme::new(settings.theme);
+    if displays.iter().any(|display| display.is_monochrome()) {
+        theme.monochrome()
+    } else {
+        theme
+    }
+}
+
+/// Whether the screensaver clock is shown in place of a blank display.
+fn showing_screensaver(contents: &Contents) -> bool {
+    contents.blank && contents.started && contents.settings.screensaver
+}
+
+/// Turn the displays on or off, and set their brightness, for whether they
+/// are blank and whether the screensaver is shown.
+fn set_power<D>(displays: &mut [D], contents: &Contents) -> Result<()>
+where
+    D: FlushableDrawTarget,
+    D::Error: std::fmt::Debug,
+{
+    let screensaver = showing_screensaver(contents);
+    let brightness = if screensaver {
+        min(contents.brightness, SCREENSAVER_BRIGHTNESS)
+    } else {
+        contents.brightness
+    };
+    for display in displays.iter_mut() {
+        display.set_brightness(brightness).map_err(display_error)?;
+        display
+            .set_display_on(!contents.blank || screensaver)
+            .map_err(display_error)?;
+    }
+    Ok(())
+}
+
+fn display_error<E: std::fmt::Debug>(err: E) -> anyhow::Error {
+    anyhow!("Display error: {err:?}")
 }
 
 pub fn display_thread<D, const NUM_PER_PAGE: usize, const NUM_DISPLAYS: usize>(
     displays: &mut [D; NUM_DISPLAYS],
     components: &[Button; NUM_PER_PAGE],
-    rx: mpsc::Receiver<DisplayCommand>,
-) where
+    status_bar: Option<&StatusBar>,
+    rx: &mpsc::Receiver<DisplayCommand>,
+    contents: &mut Contents,
+) -> Result<()>
+where
     D: FlushableDrawTarget,
     D::Color: PixelColor + From<Gray8> + From<Rgb555> + From<Rgb888>,
     D::Error: std::fmt::Debug,
 {
-    let mut states: Vec<Option<State>> = vec![None; NUM_PER_PAGE];
+    contents.states.resize(NUM_PER_PAGE, None);
+    contents.theme = get_theme(&contents.settings, displays);
 
+    set_power(displays, contents)?;
     for display in displays.iter_mut() {
-        display.set_display_on(true).unwrap();
-        led_draw_loading(display);
-        display.flush().unwrap();
+        if !contents.started {
+            led_draw_loading(display, &contents.theme).map_err(display_error)?;
+            display.flush().map_err(display_error)?;
+        }
     }
 
-    for received in rx {
-        let mut update_components: [bool; NUM_PER_PAGE] = [false; NUM_PER_PAGE];
+    // If this is a restart, put back what was there before.
+    let mut redraw = contents.started.then_some(DisplayCommand::Started);
+    // When to take down the status bar, if it is a popup that is up.
+    let mut popup_until: Option<Instant> = None;
+    // When to next move along the names that are scrolling, if any are.
+    let mut marquee_next: Option<Instant> = None;
+
+    loop {
+        let mut updates = Updates {
+            components: [false; NUM_PER_PAGE],
+            status: false,
+            indicator: false,
+            screensaver: false,
+        };
+
+        let deadline = [popup_until, marquee_next].into_iter().flatten().min();
+        let received = match (redraw.take(), deadline) {
+            (Some(command), _) => Some(command),
+            (None, Some(until)) => {
+                match rx.recv_timeout(until.saturating_duration_since(Instant::now())) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            (None, None) => match rx.recv() {
+                Ok(command) => Some(command),
+                Err(_) => break,
+            },
+        };
+
+        let Some(received) = received else {
+            let now = Instant::now();
+            if popup_until.map_or(false, |until| until <= now) {
+                // The popup is done, so put back what it covered.
+                popup_until = None;
+                if let Some(status_bar) = status_bar {
+                    for (id, component) in components.iter().enumerate() {
+                        updates.components[id] = component.display == status_bar.display;
+                    }
+                }
+                updates.indicator = true;
+            }
+            if marquee_next.map_or(false, |next| next <= now) {
+                marquee_next = Some(now + MARQUEE_STEP);
+                for (id, state) in contents.states.iter_mut().enumerate() {
+                    if let Some(State {
+                        scroll: Some(offset),
+                        ..
+                    }) = state
+                    {
+                        *offset += 1;
+                        updates.components[id] = true;
+                    }
+                }
+            }
+            let popup = popup_until.is_some();
+            draw_updates(displays, components, status_bar, popup, contents, &updates)?;
+            continue;
+        };
+
+        let states = &mut contents.states;
 
         match received {
+            DisplayCommand::Settings(settings) => {
+                if !settings.marquee {
+                    for (id, state) in states.iter_mut().enumerate() {
+                        if let Some(state) = state {
+                            updates.components[id] = state.scroll.take().is_some();
+                        }
+                    }
+                }
+                let theme_changed = settings.theme != contents.settings.theme;
+                let screensaver_changed = settings.screensaver != contents.settings.screensaver;
+                contents.settings = settings;
+                if screensaver_changed {
+                    set_power(displays, contents)?;
+                    if contents.blank && contents.started {
+                        // Draw the clock, or put back the page it covered.
+                        redraw = Some(DisplayCommand::Started);
+                    }
+                }
+                if theme_changed {
+                    contents.theme = get_theme(&contents.settings, displays);
+                    // Draw everything again in the new colours.
+                    if contents.started {
+                        redraw = Some(DisplayCommand::Started);
+                    }
+                }
+            }
             DisplayCommand::Started => {
+                contents.started = true;
+                set_power(displays, contents)?;
                 for display in displays.iter_mut() {
-                    display.clear(Rgb555::BLUE.into()).unwrap();
-                    display.flush().unwrap();
+                    display
+                        .clear(contents.theme.started.into())
+                        .map_err(display_error)?;
+                }
+                updates.components = [true; NUM_PER_PAGE];
+                updates.status = true;
+                updates.indicator = true;
+                updates.screensaver = true;
+            }
+            DisplayCommand::Error(message) => {
+                for display in displays.iter_mut() {
+                    led_draw_error(display, &message, &contents.theme).map_err(display_error)?;
+                    display.flush().map_err(display_error)?;
                 }
             }
             DisplayCommand::DisplayState(state, icon, id, name) => {
-                let pressed = if let Some(old) = &states[id] {
-                    old.pressed
+                let (pressed, scroll) = if let Some(old) = &states[id] {
+                    (old.pressed, old.scroll)
                 } else {
-                    false
+                    (false, None)
                 };
 
                 let page = State {
@@ -73,58 +302,413 @@ pub fn display_thread<D, const NUM_PER_PAGE: usize, const NUM_DISPLAYS: usize>(
                     icon,
                     name,
                     pressed,
+                    queued: false,
+                    scroll,
                 };
                 states[id] = Some(page);
-                update_components[id] = true;
+                updates.components[id] = true;
             }
             DisplayCommand::DisplayNone(id) => {
                 states[id] = None;
-                update_components[id] = true;
+                updates.components[id] = true;
             }
             DisplayCommand::BlankAll => {
-                for display in displays.iter_mut() {
-                    display.set_display_on(false).unwrap();
-                }
+                contents.blank = true;
+                set_power(displays, contents)?;
+                updates.screensaver = true;
+            }
+            DisplayCommand::SetBrightness(level) => {
+                contents.brightness = level;
+                set_power(displays, contents)?;
             }
             DisplayCommand::UnBlankAll => {
-                for display in displays.iter_mut() {
-                    display.set_display_on(true).unwrap();
+                let screensaver = showing_screensaver(contents);
+                contents.blank = false;
+                set_power(displays, contents)?;
+                if screensaver {
+                    // Put back the page the clock was drawn over.
+                    redraw = Some(DisplayCommand::Started);
                 }
-                // update_components = [true; NUM_PER_PAGE];
+                // updates.components = [true; NUM_PER_PAGE];
             }
-            DisplayCommand::ShowPage(_page_num) => {
-                update_components = [false; NUM_PER_PAGE];
+            DisplayCommand::ShowPage(page_num, num_pages, text) => {
+                contents.title = Some(text);
+                contents.page = Some((page_num, num_pages));
+                #[cfg(feature = "lca2021_badge")]
+                if let Some(StatusStyle::Popup { duration, .. }) = status_bar.map(|s| &s.style) {
+                    popup_until = Some(Instant::now() + *duration);
+                }
+                updates.status = true;
             }
             DisplayCommand::ButtonPressed(id) => {
                 if let Some(page) = &mut states[id] {
                     page.pressed = true;
+                    let overflows = !label::fits(&page.name, components[id].get_label_chars());
+                    page.scroll = (contents.settings.marquee && overflows).then_some(0);
                 }
-                update_components[id] = true;
+                updates.components[id] = true;
             }
             DisplayCommand::ButtonReleased(id) => {
                 if let Some(page) = &mut states[id] {
                     page.pressed = false;
+                    page.scroll = None;
                 }
-                update_components[id] = true;
+                updates.components[id] = true;
             }
+            DisplayCommand::ButtonQueued(id, queued) => {
+                if let Some(page) = &mut states[id] {
+                    page.queued = queued;
+                }
+                updates.components[id] = true;
+            }
+            DisplayCommand::Connection(new_connection) => {
+                contents.connection = new_connection;
+                updates.status = true;
+                updates.indicator = true;
+            }
+            DisplayCommand::Status(status) => {
+                if showing_screensaver(contents) {
+                    // Move the clock on every tick, so no pixel stays lit.
+                    contents.screensaver_step = contents.screensaver_step.wrapping_add(1);
+                    updates.screensaver = true;
+                }
+                contents.status = status;
+                updates.status = true;
+            }
+        }
+
+        let scrolling = contents.states.iter().flatten().any(|s| s.scroll.is_some());
+        if !scrolling {
+            marquee_next = None;
+        } else if marquee_next.is_none() {
+            marquee_next = Some(Instant::now() + MARQUEE_STEP);
         }
 
-        for (id, component) in components.iter().enumerate() {
-            let state = &states[id];
-            if update_components[id] {
-                component.draw(displays, state);
+        let popup = popup_until.is_some();
+        draw_updates(displays, components, status_bar, popup, contents, &updates)?;
+    }
+
+    Ok(())
+}
+
+/// What needs to be drawn again.
+struct Updates<const NUM_PER_PAGE: usize> {
+    components: [bool; NUM_PER_PAGE],
+    status: bool,
+    indicator: bool,
+    screensaver: bool,
+}
+
+/// Draw what has changed, and flush it to the displays.
+///
+/// While the status bar is up as a popup, nothing else on its display is
+/// drawn. While the screensaver is shown, only it is drawn, and the page is
+/// drawn again on waking.
+fn draw_updates<D, const NUM_PER_PAGE: usize>(
+    displays: &mut [D],
+    components: &[Button; NUM_PER_PAGE],
+    status_bar: Option<&StatusBar>,
+    popup: bool,
+    contents: &Contents,
+    updates: &Updates<NUM_PER_PAGE>,
+) -> Result<()>
+where
+    D: FlushableDrawTarget,
+    D::Color: PixelColor + From<Gray8> + From<Rgb555> + From<Rgb888>,
+    D::Error: std::fmt::Debug,
+{
+    if showing_screensaver(contents) {
+        if updates.screensaver {
+            for (index, display) in displays.iter_mut().enumerate() {
+                let step = contents.screensaver_step.wrapping_add(index as u32 * 7);
+                let time = contents.status.time.as_deref().unwrap_or("--:--");
+                led_draw_clock(display, time, step, &contents.theme).map_err(display_error)?;
+                display.flush().map_err(display_error)?;
             }
         }
+        return Ok(());
+    }
+
+    let covered = |display: usize| popup && status_bar.map(|s| s.display) == Some(display);
+
+    for (id, component) in components.iter().enumerate() {
+        let state = &contents.states[id];
+        if updates.components[id] && !covered(component.display) {
+            component
+                .draw(displays, state, contents.icons.as_ref(), &contents.theme)
+                .map_err(display_error)?;
+        }
+    }
+
+    if let Some(status_bar) = status_bar {
+        let shown = match status_bar.style {
+            #[cfg(any(feature = "makerfab", feature = "simulator"))]
+            StatusStyle::Bar => true,
+            #[cfg(feature = "lca2021_badge")]
+            StatusStyle::Popup { .. } => popup,
+        };
+        if updates.status && shown {
+            status_bar.draw(displays, contents).map_err(display_error)?;
+        }
+    }
 
-        if update_components.iter().any(|x| *x) {
-            for display in displays.iter_mut() {
-                display.flush().unwrap();
+    // The indicator might overlap a button, so draw it after them.
+    let updated = updates.status || updates.indicator || updates.components.iter().any(|x| *x);
+    if let Some(status_bar) = status_bar {
+        if let Some(indicator) = status_bar.get_indicator() {
+            if updated && !covered(status_bar.display) {
+                let display = &mut displays[status_bar.display];
+                draw_indicator(display, indicator, &contents.connection, &contents.theme)
+                    .map_err(display_error)?;
             }
         }
+    }
+
+    if updated {
+        for display in displays.iter_mut() {
+            display.flush().map_err(display_error)?;
+        }
+        trace!("Done flushing");
+    }
+
+    Ok(())
+}
+
+fn get_connection_color(connection: &ConnectionState) -> Rgb555 {
+    match connection {
+        ConnectionState::Connected => Rgb555::GREEN,
+        ConnectionState::Connecting => Rgb555::YELLOW,
+        ConnectionState::Reconnecting => Rgb555::new(31, 15, 0),
+        ConnectionState::AuthFailed => Rgb555::RED,
+    }
+}
+
+/// Signal strength as a number of bars out of 4.
+#[cfg(any(feature = "makerfab", feature = "simulator"))]
+fn get_wifi_bars(rssi: Option<i8>) -> u32 {
+    match rssi {
+        None => 0,
+        Some(rssi) if rssi >= -55 => 4,
+        Some(rssi) if rssi >= -65 => 3,
+        Some(rssi) if rssi >= -75 => 2,
+        Some(_) => 1,
+    }
+}
+
+/// How to show a [`StatusBar`].
+pub enum StatusStyle {
+    /// Always shown, as one line.
+    #[cfg(any(feature = "makerfab", feature = "simulator"))]
+    Bar,
+    /// Shown over everything else on its display for `duration` after the
+    /// page changes, for boards that have no room for it. The rest of the
+    /// time, the state of the MQTT connection is shown in `indicator`.
+    #[cfg(feature = "lca2021_badge")]
+    Popup {
+        duration: Duration,
+        indicator: Rectangle,
+    },
+}
+
+/// Area of a display used to show the time, the page, the WiFi signal and
+/// the state of the MQTT connection.
+pub struct StatusBar {
+    display: usize,
+    bounding_box: Rectangle,
+    style: StatusStyle,
+}
+
+impl StatusBar {
+    pub fn new(display: usize, bounding_box: Rectangle, style: StatusStyle) -> StatusBar {
+        StatusBar {
+            display,
+            bounding_box,
+            style,
+        }
+    }
+
+    fn draw<D>(&self, displays: &mut [D], contents: &Contents) -> Result<(), D::Error>
+    where
+        D: FlushableDrawTarget,
+        D::Color: From<Rgb555>,
+        D::Error: std::fmt::Debug,
+    {
+        let display = &mut displays[self.display];
+        led_clear(display, &self.bounding_box, &contents.theme)?;
+
+        match self.style {
+            #[cfg(any(feature = "makerfab", feature = "simulator"))]
+            StatusStyle::Bar => self.draw_bar(display, contents),
+            #[cfg(feature = "lca2021_badge")]
+            StatusStyle::Popup { .. } => self.draw_popup(display, contents),
+        }
+    }
+
+    /// Where to show the state of the MQTT connection while the status bar
+    /// is not. A bar shows it itself.
+    fn get_indicator(&self) -> Option<&Rectangle> {
+        match &self.style {
+            #[cfg(any(feature = "makerfab", feature = "simulator"))]
+            StatusStyle::Bar => None,
+            #[cfg(feature = "lca2021_badge")]
+            StatusStyle::Popup { indicator, .. } => Some(indicator),
+        }
+    }
+
+    #[cfg(any(feature = "makerfab", feature = "simulator"))]
+    fn draw_bar<D>(&self, display: &mut D, contents: &Contents) -> Result<(), D::Error>
+    where
+        D: FlushableDrawTarget,
+        D::Color: From<Rgb555>,
+        D::Error: std::fmt::Debug,
+    {
+        let top_left = self.bounding_box.top_left;
+        let bottom_right = self.bounding_box.bottom_right().unwrap();
+        let center = self.bounding_box.center();
+        let style = MonoTextStyle::new(&FONT_10X20, contents.theme.label.into());
+
+        let time = contents.status.time.as_deref().unwrap_or("--:--");
+        Text::new(time, Point::new(top_left.x + 2, top_left.y + 15), style).draw(display)?;
+
+        if let (Some(title), Some((page_num, num_pages))) = (&contents.title, contents.page) {
+            let text = format!("{} {}/{}", title, page_num + 1, num_pages);
+            Text::with_alignment(
+                &text,
+                Point::new(center.x, top_left.y + 15),
+                style,
+                Alignment::Center,
+            )
+            .draw(display)?;
+        }
+
+        // The MQTT state as a dot at the right.
+        let diameter = 14;
+        let dot = Point::new(
+            bottom_right.x - diameter as i32,
+            center.y - diameter as i32 / 2,
+        );
+        let color = get_connection_color(&contents.connection);
+        Circle::new(dot, diameter)
+            .into_styled(PrimitiveStyle::with_fill(color.into()))
+            .draw(display)?;
+
+        // And the WiFi signal as bars to the left of it.
+        let bars = get_wifi_bars(contents.status.rssi);
+        for bar in 0..4 {
+            let height = 4 * (bar + 1);
+            let x = dot.x - 26 + 5 * bar as i32;
+            let color = if bar < bars {
+                contents.theme.label
+            } else {
+                Rgb555::new(8, 8, 8)
+            };
+            Rectangle::new(
+                Point::new(x, center.y + 8 - height as i32),
+                Size::new(3, height),
+            )
+            .into_styled(PrimitiveStyle::with_fill(color.into()))
+            .draw(display)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "lca2021_badge")]
+    fn draw_popup<D>(&self, display: &mut D, contents: &Contents) -> Result<(), D::Error>
+    where
+        D: FlushableDrawTarget,
+        D::Color: From<Rgb555>,
+        D::Error: std::fmt::Debug,
+    {
+        let top = self.bounding_box.top_left.y;
+        let center = self.bounding_box.center();
+        let label = contents.theme.label;
+        let small = MonoTextStyle::new(&FONT_6X10, label.into());
+
+        if let Some(title) = &contents.title {
+            Text::with_alignment(
+                title,
+                Point::new(center.x, top + 15),
+                MonoTextStyle::new(&FONT_10X20, label.into()),
+                Alignment::Center,
+            )
+            .draw(display)?;
+        }
+
+        let page = match contents.page {
+            Some((page_num, num_pages)) => format!("Page {} of {}", page_num + 1, num_pages),
+            None => String::new(),
+        };
+        let time = contents.status.time.as_deref().unwrap_or("--:--");
+        let wifi = match contents.status.rssi {
+            Some(rssi) => format!("WiFi {rssi}dBm"),
+            None => "No WiFi".to_string(),
+        };
+        let mqtt = match &contents.connection {
+            ConnectionState::Connected => "MQTT connected".to_string(),
+            ConnectionState::Connecting => "MQTT connecting".to_string(),
+            ConnectionState::Reconnecting => "MQTT reconnecting".to_string(),
+            ConnectionState::AuthFailed => "MQTT auth failed".to_string(),
+        };
+
+        let lines = [page, format!("{time} {wifi}"), mqtt];
+        for (i, line) in lines.iter().enumerate() {
+            Text::with_alignment(
+                line,
+                Point::new(center.x, top + 32 + 12 * i as i32),
+                small,
+                Alignment::Center,
+            )
+            .draw(display)?;
+        }
 
-        info!("Done flushing");
+        Ok(())
     }
 }
+
+/// Show the state of the MQTT connection in `bounding_box`.
+fn draw_indicator<D>(
+    display: &mut D,
+    bounding_box: &Rectangle,
+    connection: &ConnectionState,
+    theme: &Theme,
+) -> Result<(), D::Error>
+where
+    D: FlushableDrawTarget,
+    D::Color: From<Rgb555>,
+    D::Error: std::fmt::Debug,
+{
+    led_clear(display, bounding_box, theme)?;
+
+    let color = get_connection_color(connection);
+    let text = match connection {
+        ConnectionState::Connected => "MQTT".to_string(),
+        ConnectionState::Connecting => "...".to_string(),
+        ConnectionState::Reconnecting => "retry".to_string(),
+        ConnectionState::AuthFailed => "auth".to_string(),
+    };
+
+    let size = bounding_box.size;
+    let diameter = min(min(size.width, size.height), 24);
+    let center = bounding_box.center();
+    let top_left = Point::new(center.x - diameter as i32 / 2, bounding_box.top_left.y);
+    Circle::new(top_left, diameter)
+        .into_styled(PrimitiveStyle::with_fill(color.into()))
+        .draw(display)?;
+
+    // Only if there is room for it.
+    if size.height >= diameter + 12 {
+        Text::with_alignment(
+            &text,
+            Point::new(center.x, top_left.y + diameter as i32 + 10),
+            MonoTextStyle::new(&FONT_6X10, color.into()),
+            Alignment::Center,
+        )
+        .draw(display)?;
+    }
+    Ok(())
+}
+
 pub struct Button {
     display: usize,
     bounding_box: Rectangle,
@@ -138,7 +722,17 @@ impl Button {
         }
     }
 
-    fn draw<D>(&self, displays: &mut [D], state: &Option<State>)
+    fn get_label_chars(&self) -> usize {
+        get_label_chars(self.bounding_box.size.width)
+    }
+
+    fn draw<D>(
+        &self,
+        displays: &mut [D],
+        state: &Option<State>,
+        icons: Option<&Pack>,
+        theme: &Theme,
+    ) -> Result<(), D::Error>
     where
         D: FlushableDrawTarget,
         D::Color: PixelColor + From<Gray8> + From<Rgb555> + From<Rgb888>,
@@ -151,7 +745,8 @@ impl Button {
             top_left: Point::zero(),
             size: Size::new(128, 64),
         };
-        page_draw(&mut fbuff, state, &bounding_box);
+        // Drawing to the frame buffer cannot fail.
+        page_draw(&mut fbuff, state, icons, theme, &bounding_box).unwrap();
 
         let display = &mut displays[self.display];
 
@@ -159,44 +754,58 @@ impl Button {
             let c: D::Color = c.1.into();
             c
         });
-        display
-            .fill_contiguous(&self.bounding_box, u16_iter)
-            .unwrap();
+        display.fill_contiguous(&self.bounding_box, u16_iter)?;
+        Ok(())
     }
 }
 
-fn page_draw<D>(display: &mut D, state_or_none: &Option<State>, bounding_box: &Rectangle)
+fn page_draw<D>(
+    display: &mut D,
+    state_or_none: &Option<State>,
+    icons: Option<&Pack>,
+    theme: &Theme,
+    bounding_box: &Rectangle,
+) -> Result<(), D::Error>
 where
     D: DrawTarget,
     D::Color: PixelColor + From<Gray8> + From<Rgb555> + From<Rgb888>,
     D::Error: std::fmt::Debug,
 {
-    led_clear(display, bounding_box);
+    led_clear(display, bounding_box, theme)?;
 
     if let Some(state) = state_or_none {
         let image_category = get_image_category(&state.state);
-        let image_data = get_image_data(&image_category, &state.icon);
-        led_draw_image(display, image_data, bounding_box);
-        led_draw_overlay(display, &state.state, bounding_box);
-        led_draw_name(display, &state.name, bounding_box);
+        if let Some(image_data) = get_image_data(&image_category, &state.icon, icons) {
+            let mut tinted = Tinted {
+                display: &mut *display,
+                tint: theme.icon,
+            };
+            led_draw_image(&mut tinted, image_data, bounding_box)?;
+        }
+        led_draw_accent(display, &state.state, theme, bounding_box)?;
+        led_draw_overlay(display, &state.state, theme, bounding_box)?;
+        led_draw_name(display, state, theme, bounding_box)?;
+        if state.queued {
+            led_draw_queued(display, theme, bounding_box)?;
+        }
         if state.pressed {
-            led_draw_pressed(display, bounding_box);
+            led_draw_pressed(display, theme, bounding_box)?;
         }
     }
+    Ok(())
 }
 
-fn led_clear<D>(display: &mut D, bounding_box: &Rectangle)
+fn led_clear<D>(display: &mut D, bounding_box: &Rectangle, theme: &Theme) -> Result<(), D::Error>
 where
     D: DrawTarget,
     D::Color: From<Rgb555>,
     D::Error: std::fmt::Debug,
 {
-    display
-        .fill_solid(bounding_box, Rgb555::BLACK.into())
-        .unwrap();
+    display.fill_solid(bounding_box, theme.background.into())?;
+    Ok(())
 }
 
-fn led_draw_loading<D>(display: &mut D)
+fn led_draw_loading<D>(display: &mut D, theme: &Theme) -> Result<(), D::Error>
 where
     D: DrawTarget,
     D::Color: From<Rgb555>,
@@ -206,26 +815,54 @@ where
         .bounding_box()
         .into_styled(
             PrimitiveStyleBuilder::new()
-                .fill_color(Rgb555::RED.into())
-                .stroke_color(Rgb555::YELLOW.into())
+                .fill_color(theme.loading_fill.into())
+                .stroke_color(theme.loading_outline.into())
                 .stroke_width(1)
                 .build(),
         )
-        .draw(display)
-        .unwrap();
+        .draw(display)?;
 
     let t = "Loading";
 
     Text::new(
         t,
         Point::new(10, (display.bounding_box().size.height - 10) as i32 / 2),
-        MonoTextStyle::new(&FONT_10X20, Rgb555::WHITE.into()),
+        MonoTextStyle::new(&FONT_10X20, theme.loading_text.into()),
     )
-    .draw(display)
-    .unwrap();
+    .draw(display)?;
+    Ok(())
 }
 
-fn led_draw_pressed<D>(display: &mut D, bounding_box: &Rectangle)
+fn led_draw_error<D>(display: &mut D, message: &str, theme: &Theme) -> Result<(), D::Error>
+where
+    D: DrawTarget,
+    D::Color: From<Rgb555>,
+    D::Error: std::fmt::Debug,
+{
+    display.clear(theme.error_screen.into())?;
+
+    let bounding_box = display.bounding_box();
+    let chars_per_line = max((bounding_box.size.width as usize / 6).saturating_sub(1), 1);
+    let chars: Vec<char> = message.chars().collect();
+    let lines: Vec<String> = chars
+        .chunks(chars_per_line)
+        .map(|line| line.iter().collect())
+        .collect();
+
+    Text::new(
+        &lines.join("\n"),
+        Point::new(3, 10),
+        MonoTextStyle::new(&FONT_6X10, theme.label.into()),
+    )
+    .draw(display)?;
+    Ok(())
+}
+
+fn led_draw_pressed<D>(
+    display: &mut D,
+    theme: &Theme,
+    bounding_box: &Rectangle,
+) -> Result<(), D::Error>
 where
     D: DrawTarget,
     D::Color: From<Rgb555>,
@@ -235,30 +872,84 @@ where
         .into_styled(
             PrimitiveStyleBuilder::new()
                 .reset_fill_color()
-                .stroke_color(Rgb555::GREEN.into())
+                .stroke_color(theme.pressed.into())
                 .stroke_width(1)
                 .build(),
         )
-        .draw(display)
-        .unwrap();
+        .draw(display)?;
+    Ok(())
 }
 
-fn led_draw_name<D>(display: &mut D, name: &str, bounding_box: &Rectangle)
+fn led_draw_queued<D>(
+    display: &mut D,
+    theme: &Theme,
+    bounding_box: &Rectangle,
+) -> Result<(), D::Error>
 where
     D: DrawTarget,
     D::Color: From<Rgb555>,
     D::Error: std::fmt::Debug,
 {
+    let ul = bounding_box.top_left + Point::new(2, 2);
+
+    Rectangle::new(ul, Size::new(42, 14))
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(theme.background.into())
+                .stroke_color(theme.queued.into())
+                .stroke_width(1)
+                .build(),
+        )
+        .draw(display)?;
+
     Text::new(
-        name,
-        Point::new(
-            bounding_box.top_left.x + 2,
-            bounding_box.bottom_right().unwrap().y - 4,
-        ),
-        MonoTextStyle::new(&FONT_5X8, Rgb555::WHITE.into()),
+        "Queued",
+        ul + Point::new(3, 10),
+        MonoTextStyle::new(&FONT_6X10, theme.queued.into()),
     )
-    .draw(display)
-    .unwrap();
+    .draw(display)?;
+    Ok(())
+}
+
+/// How many characters of a name fit across a tile this wide.
+fn get_label_chars(width: u32) -> usize {
+    let char_width = FONT_5X8.character_size.width + FONT_5X8.character_spacing;
+    (width.saturating_sub(4) / char_width) as usize
+}
+
+/// Draw the name along the bottom of the tile, over two lines if it needs
+/// them and the overlay is not using that room.
+fn led_draw_name<D>(
+    display: &mut D,
+    state: &State,
+    theme: &Theme,
+    bounding_box: &Rectangle,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget,
+    D::Color: From<Rgb555>,
+    D::Error: std::fmt::Debug,
+{
+    let max_chars = get_label_chars(bounding_box.size.width);
+    let lines = match state.scroll {
+        Some(offset) => vec![label::scroll(&state.name, max_chars, offset)],
+        None if has_overlay(&state.state) => label::wrap(&state.name, max_chars, 1),
+        None => label::wrap(&state.name, max_chars, 2),
+    };
+
+    let line_height = FONT_5X8.character_size.height as i32;
+    let bottom = bounding_box.bottom_right().unwrap().y - 4;
+    let style = MonoTextStyle::new(&FONT_5X8, theme.label.into());
+
+    for (i, line) in lines.iter().rev().enumerate() {
+        Text::new(
+            line,
+            Point::new(bounding_box.top_left.x + 2, bottom - line_height * i as i32),
+            style,
+        )
+        .draw(display)?;
+    }
+    Ok(())
 }
 
 enum ImageCategory {
@@ -282,64 +973,180 @@ fn get_image_category(state: &DisplayState) -> ImageCategory {
 fn get_image_data<T: PixelColor + From<Gray8> + From<Rgb555> + From<Rgb888>>(
     image: &ImageCategory,
     icon: &Icon,
-) -> impl ImageDrawable<Color = T> {
-    use ImageCategory::*;
-
-    let data = match icon {
-        Icon::Fan => match image {
-            Error => include_bytes!("images/fan_error_64x64.tga").as_slice(),
-            On => include_bytes!("images/fan_on_64x64.tga").as_slice(),
-            Off => include_bytes!("images/fan_off_64x64.tga").as_slice(),
-            AutoOff => include_bytes!("images/fan_auto_64x64.tga").as_slice(),
-        },
-        Icon::Light => match image {
-            Error => include_bytes!("images/light_error_64x64.tga").as_slice(),
-            On => include_bytes!("images/light_on_64x64.tga").as_slice(),
-            Off => include_bytes!("images/light_off_64x64.tga").as_slice(),
-            AutoOff => include_bytes!("images/light_auto_64x64.tga").as_slice(),
-        },
-        Icon::Night => match image {
-            Error => include_bytes!("images/night_error_64x64.tga").as_slice(),
-            On => include_bytes!("images/night_on_64x64.tga").as_slice(),
-            Off => include_bytes!("images/night_off_64x64.tga").as_slice(),
-            AutoOff => include_bytes!("images/night_auto_64x64.tga").as_slice(),
-        },
-        Icon::Schedule => match image {
-            Error => include_bytes!("images/schedule_error_64x64.tga").as_slice(),
-            On => include_bytes!("images/schedule_on_64x64.tga").as_slice(),
-            Off => include_bytes!("images/schedule_off_64x64.tga").as_slice(),
-            AutoOff => include_bytes!("images/schedule_auto_64x64.tga").as_slice(),
-        },
-        Icon::Select => match image {
-            Error => include_bytes!("images/select_error_64x64.tga").as_slice(),
-            On => include_bytes!("images/select_on_64x64.tga").as_slice(),
-            Off => include_bytes!("images/select_off_64x64.tga").as_slice(),
-            AutoOff => include_bytes!("images/select_auto_64x64.tga").as_slice(),
-        },
-        Icon::Speaker => match image {
-            Error => include_bytes!("images/speaker_error_64x64.tga").as_slice(),
-            On => include_bytes!("images/speaker_on_64x64.tga").as_slice(),
-            Off => include_bytes!("images/speaker_off_64x64.tga").as_slice(),
-            AutoOff => include_bytes!("images/speaker_auto_64x64.tga").as_slice(),
-        },
-        Icon::Trumpet => match image {
-            Error => include_bytes!("images/trumpet_error_64x64.tga").as_slice(),
-            On => include_bytes!("images/trumpet_on_64x64.tga").as_slice(),
-            Off => include_bytes!("images/trumpet_off_64x64.tga").as_slice(),
-            AutoOff => include_bytes!("images/trumpet_auto_64x64.tga").as_slice(),
-        },
-        Icon::TV => match image {
-            Error => include_bytes!("images/tv_error_64x64.tga").as_slice(),
-            On => include_bytes!("images/tv_on_64x64.tga").as_slice(),
-            Off => include_bytes!("images/tv_off_64x64.tga").as_slice(),
-            AutoOff => include_bytes!("images/tv_auto_64x64.tga").as_slice(),
-        },
+    icons: Option<&Pack>,
+) -> Option<impl ImageDrawable<Color = T>> {
+    let state = match image {
+        ImageCategory::Error => "error",
+        ImageCategory::On => "on",
+        ImageCategory::Off => "off",
+        ImageCategory::AutoOff => "auto",
     };
 
-    DynamicTga::from_slice(data).unwrap()
+    let data = icons?.get(icon.name(), state)?;
+    DynamicTga::from_slice(data).ok()
 }
 
-fn led_draw_image<D, I, C>(display: &mut D, tga: I, bounding_box: &Rectangle)
+/// Distance along a line of `range` pixels after `steps`, going back and
+/// forth between the ends.
+fn bounce(steps: u32, range: u32) -> u32 {
+    if range == 0 {
+        return 0;
+    }
+    let position = steps % (2 * range);
+    if position <= range {
+        position
+    } else {
+        2 * range - position
+    }
+}
+
+/// Draw the time as large as fits in half the width, somewhere along a path
+/// that moves with each step.
+fn led_draw_clock<D>(display: &mut D, time: &str, step: u32, theme: &Theme) -> Result<(), D::Error>
+where
+    D: DrawTarget,
+    D::Color: From<Rgb555>,
+    D::Error: std::fmt::Debug,
+{
+    display.clear(theme.background.into())?;
+
+    let font = &FONT_10X20;
+    let text_width = font.character_size.width * time.chars().count() as u32;
+    let text_height = font.character_size.height;
+
+    let size = display.bounding_box().size;
+    let factor = max(
+        1,
+        min(
+            size.width / 2 / max(text_width, 1),
+            size.height / text_height,
+        ),
+    );
+    let free_width = size.width.saturating_sub(text_width * factor);
+    let free_height = size.height.saturating_sub(text_height * factor);
+
+    // Across faster than down, so it covers the free area rather than
+    // going back and forth along one line.
+    let origin = Point::new(
+        bounce(step * 5, free_width) as i32,
+        bounce(step * 3, free_height) as i32,
+    );
+
+    let mut scaled = Scaled {
+        display,
+        origin,
+        factor,
+    };
+    Text::with_baseline(
+        time,
+        Point::zero(),
+        MonoTextStyle::new(font, theme.label.into()),
+        Baseline::Top,
+    )
+    .draw(&mut scaled)?;
+    Ok(())
+}
+
+/// Draws every pixel as a square of `factor` pixels across, from `origin`.
+struct Scaled<'a, D> {
+    display: &'a mut D,
+    origin: Point,
+    factor: u32,
+}
+
+impl<D: DrawTarget> Dimensions for Scaled<'_, D> {
+    fn bounding_box(&self) -> Rectangle {
+        let size = self.display.bounding_box().size;
+        Rectangle::new(
+            Point::zero(),
+            Size::new(size.width / self.factor, size.height / self.factor),
+        )
+    }
+}
+
+impl<D: DrawTarget> DrawTarget for Scaled<'_, D> {
+    type Color = D::Color;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let square = Size::new(self.factor, self.factor);
+        for Pixel(point, color) in pixels {
+            let top_left = self.origin + point * self.factor as i32;
+            self.display
+                .fill_solid(&Rectangle::new(top_left, square), color)?;
+        }
+        Ok(())
+    }
+}
+
+/// Draws greyscale images in shades of a colour rather than of grey.
+struct Tinted<'a, D> {
+    display: &'a mut D,
+    tint: Rgb555,
+}
+
+impl<D: DrawTarget> Dimensions for Tinted<'_, D> {
+    fn bounding_box(&self) -> Rectangle {
+        self.display.bounding_box()
+    }
+}
+
+impl<D> DrawTarget for Tinted<'_, D>
+where
+    D: DrawTarget,
+    D::Color: From<Rgb555>,
+{
+    type Color = Rgb888;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let tint = self.tint;
+        let scale = |channel: u8, level: u8| (channel as u16 * level as u16 / 255) as u8;
+        let pixels = pixels.into_iter().map(|Pixel(point, color)| {
+            // Grey, so every channel has the same level.
+            let level = color.g();
+            let color = Rgb555::new(
+                scale(tint.r(), level),
+                scale(tint.g(), level),
+                scale(tint.b(), level),
+            );
+            Pixel(point, color.into())
+        });
+        self.display.draw_iter(pixels)
+    }
+}
+
+/// Draw a strip down the left of the tile in the accent for its state.
+fn led_draw_accent<D>(
+    display: &mut D,
+    state: &DisplayState,
+    theme: &Theme,
+    bounding_box: &Rectangle,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget,
+    D::Color: From<Rgb555>,
+    D::Error: std::fmt::Debug,
+{
+    let strip = Rectangle::new(
+        bounding_box.top_left,
+        Size::new(2, bounding_box.size.height),
+    );
+    display.fill_solid(&strip, theme.get_accent(state).into())?;
+    Ok(())
+}
+
+fn led_draw_image<D, I, C>(
+    display: &mut D,
+    tga: I,
+    bounding_box: &Rectangle,
+) -> Result<(), D::Error>
 where
     D: DrawTarget<Color = C>,
     D::Error: std::fmt::Debug,
@@ -354,10 +1161,20 @@ where
     );
     let y = max(center.y - size.height as i32 / 2, bounding_box.top_left.y);
 
-    Image::new(&tga, Point::new(x, y)).draw(display).unwrap();
+    Image::new(&tga, Point::new(x, y)).draw(display)?;
+    Ok(())
+}
+
+fn has_overlay(state: &DisplayState) -> bool {
+    matches!(state, DisplayState::Error | DisplayState::Unknown)
 }
 
-fn led_draw_overlay<D>(display: &mut D, state: &DisplayState, bounding_box: &Rectangle)
+fn led_draw_overlay<D>(
+    display: &mut D,
+    state: &DisplayState,
+    theme: &Theme,
+    bounding_box: &Rectangle,
+) -> Result<(), D::Error>
 where
     D: DrawTarget,
     D::Color: From<Rgb555>,
@@ -372,7 +1189,7 @@ where
         DisplayState::AutoOff => "Auto Off",
     };
 
-    if matches!(state, DisplayState::Error | DisplayState::Unknown) {
+    if has_overlay(state) {
         let center = bounding_box.center();
         let size = Size::new(60, 24);
 
@@ -383,21 +1200,20 @@ where
         Rectangle::new(ul, size)
             .into_styled(
                 PrimitiveStyleBuilder::new()
-                    .fill_color(Rgb555::BLACK.into())
-                    .stroke_color(Rgb555::WHITE.into())
+                    .fill_color(theme.background.into())
+                    .stroke_color(theme.error_box.into())
                     .stroke_width(1)
                     .build(),
             )
-            .draw(display)
-            .unwrap();
+            .draw(display)?;
 
         Text::with_alignment(
             text,
             Point::new(center.x, y + 17),
-            MonoTextStyle::new(&FONT_10X20, Rgb555::WHITE.into()),
+            MonoTextStyle::new(&FONT_10X20, theme.error_box.into()),
             Alignment::Center,
         )
-        .draw(display)
-        .unwrap();
+        .draw(display)?;
     }
+    Ok(())
 }
@@ -1,12 +1,21 @@
-#[allow(dead_code)]
-#[derive(Debug, Copy, Clone)]
-pub enum Icon {
-    Fan,
-    Light,
-    Night,
-    Schedule,
-    Select,
-    Speaker,
-    Trumpet,
-    TV,
+use serde::Deserialize;
+use serde::Serialize;
+
+/// One of the icons in the icon pack, referred to by name in the config.
+///
+/// The pack can be updated without the app, so an icon it does not have is
+/// only found out when drawing it.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Icon(String);
+
+impl Icon {
+    pub fn new(name: &str) -> Icon {
+        Icon(name.to_string())
+    }
+
+    #[cfg(any(feature = "lca2021_badge", feature = "makerfab", feature = "simulator"))]
+    pub fn name(&self) -> &str {
+        &self.0
+    }
 }
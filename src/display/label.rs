@@ -0,0 +1,182 @@
+//! Fitting the names of controllers into the space on a tile.
+
+const ELLIPSIS: &str = "...";
+
+/// Spaces between the end of the text and its start again when scrolling.
+const SCROLL_GAP: usize = 3;
+
+pub fn fits(text: &str, max_chars: usize) -> bool {
+    text.chars().count() <= max_chars
+}
+
+/// Cut `text` down to `max_chars` characters, ending with an ellipsis if
+/// anything was cut off.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    if fits(text, max_chars) {
+        return text.to_string();
+    }
+
+    let keep = max_chars.saturating_sub(ELLIPSIS.len());
+    let kept: String = text.chars().take(keep).collect();
+    let mut truncated = kept.trim_end().to_string();
+    truncated.push_str(ELLIPSIS);
+    truncated.chars().take(max_chars).collect()
+}
+
+/// Break `text` into at most `max_lines` lines of at most `max_chars`
+/// characters, at spaces where possible.
+///
+/// Words longer than a line are split. If it still does not fit, the last
+/// line ends with an ellipsis.
+pub fn wrap(text: &str, max_chars: usize, max_lines: usize) -> Vec<String> {
+    if max_chars == 0 || max_lines == 0 {
+        return Vec::new();
+    }
+
+    let mut lines: Vec<String> = Vec::new();
+    // Where each line starts in `text`, to take what does not fit from.
+    let mut starts: Vec<usize> = Vec::new();
+    let mut line = String::new();
+    let mut line_start = 0;
+    let mut end = 0;
+
+    for word in text.split_whitespace() {
+        let mut start = end + text[end..].find(word).unwrap_or(0);
+        end = start + word.len();
+        let mut word: Vec<char> = word.chars().collect();
+        loop {
+            let len = line.chars().count();
+            let needed = if len == 0 {
+                word.len()
+            } else {
+                len + 1 + word.len()
+            };
+
+            if needed <= max_chars {
+                if len == 0 {
+                    line_start = start;
+                } else {
+                    line.push(' ');
+                }
+                line.extend(word);
+                break;
+            } else if len > 0 {
+                lines.push(std::mem::take(&mut line));
+                starts.push(line_start);
+            } else {
+                let part: String = word.drain(..max_chars).collect();
+                starts.push(start);
+                start += part.len();
+                lines.push(part);
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+        starts.push(line_start);
+    }
+
+    if lines.len() > max_lines {
+        lines.truncate(max_lines - 1);
+        let rest: Vec<&str> = text[starts[max_lines - 1]..].split_whitespace().collect();
+        lines.push(truncate(&rest.join(" "), max_chars));
+    }
+    lines
+}
+
+/// The `max_chars` characters of `text` to show after scrolling it left by
+/// `offset` characters, going back to the start after the end.
+pub fn scroll(text: &str, max_chars: usize, offset: usize) -> String {
+    if fits(text, max_chars) {
+        return text.to_string();
+    }
+
+    let chars: Vec<char> = text
+        .chars()
+        .chain(std::iter::repeat(' ').take(SCROLL_GAP))
+        .collect();
+    chars
+        .iter()
+        .cycle()
+        .skip(offset % chars.len())
+        .take(max_chars)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        let cases = [
+            ("Short", 10, "Short"),
+            ("Passage Light", 13, "Passage Light"),
+            ("Passage Light", 10, "Passage..."),
+            ("Passage Light", 11, "Passage..."),
+            ("Passage Light", 12, "Passage L..."),
+            ("Müller", 5, "Mü..."),
+            ("abcdef", 2, ".."),
+            ("abc", 0, ""),
+        ];
+        for (text, max_chars, expected) in cases {
+            assert_eq!(truncate(text, max_chars), expected, "{text} in {max_chars}");
+        }
+    }
+
+    #[test]
+    fn test_wrap() {
+        let cases: &[(&str, usize, usize, &[&str])] = &[
+            ("Brian Fan", 10, 2, &["Brian Fan"]),
+            ("Brian Fan", 6, 2, &["Brian", "Fan"]),
+            ("  Brian \n Fan  ", 10, 2, &["Brian Fan"]),
+            ("", 10, 2, &[]),
+            // Words longer than a line are split.
+            ("Abcdefgh", 4, 2, &["Abcd", "efgh"]),
+            ("ab Abcdefgh", 4, 3, &["ab", "Abcd", "efgh"]),
+            // What does not fit ends in an ellipsis.
+            ("one two three four", 9, 2, &["one two", "three..."]),
+            ("one two three four", 9, 1, &["one tw..."]),
+            ("Brian Wake-Up", 6, 2, &["Brian", "Wak..."]),
+            // Without a space in the middle of a split word.
+            ("Abcdefghij klm", 8, 2, &["Abcdefgh", "ij klm"]),
+            ("a Abcdefghijklmnop", 8, 2, &["a", "Abcde..."]),
+            ("Abcdefghijklmnopqrst uv", 8, 2, &["Abcdefgh", "ijklm..."]),
+            // Characters rather than bytes.
+            ("Café Müller", 5, 2, &["Café", "Mü..."]),
+            ("Ünïcödéxyz", 4, 2, &["Ünïc", "ö..."]),
+            ("Ünïcödéxyz", 4, 3, &["Ünïc", "ödéx", "yz"]),
+            ("Brian Fan", 0, 2, &[]),
+            ("Brian Fan", 10, 0, &[]),
+        ];
+        for (text, max_chars, max_lines, expected) in cases {
+            assert_eq!(
+                wrap(text, *max_chars, *max_lines),
+                *expected,
+                "{text:?} in {max_lines} lines of {max_chars}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_scroll() {
+        let cases = [
+            ("Fan", 5, 3, "Fan"),
+            ("abcdef", 4, 0, "abcd"),
+            ("abcdef", 4, 3, "def "),
+            ("abcdef", 4, 6, "   a"),
+            // Back to the start after the text and the gap.
+            ("abcdef", 4, 9, "abcd"),
+            ("abcdef", 4, 10, "bcde"),
+            ("abcdef", 4, 90, "abcd"),
+            ("ÀÉÎÕÜ!", 3, 2, "ÎÕÜ"),
+        ];
+        for (text, max_chars, offset, expected) in cases {
+            assert_eq!(
+                scroll(text, max_chars, offset),
+                expected,
+                "{text} at {offset}"
+            );
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use std::sync::mpsc;
 use std::thread;
+use std::time::Duration;
 
+use anyhow::anyhow;
 use anyhow::Result;
 
 use embedded_graphics::prelude::Point;
@@ -12,14 +14,20 @@ use esp_idf_hal::i2c::I2cDriver;
 use ssd1306;
 use ssd1306::mode::BufferedGraphicsMode;
 use ssd1306::mode::DisplayConfig;
+use ssd1306::prelude::Brightness;
 
 use esp_idf_hal::i2c;
 use esp_idf_hal::prelude::*;
 
 use super::graphics::display_thread;
 use super::graphics::Button;
+use super::graphics::Contents;
 use super::graphics::FlushableDrawTarget;
+use super::graphics::StatusBar;
+use super::graphics::StatusStyle;
 use super::DisplayCommand;
+use crate::messages;
+use crate::supervisor;
 
 use shared_bus::BusManager;
 use shared_bus::I2cProxy;
@@ -44,6 +52,17 @@ impl FlushableDrawTarget for Display<'_> {
     fn set_display_on(&mut self, on: bool) -> Result<(), Self::Error> {
         self.set_display_on(on)
     }
+
+    fn set_brightness(&mut self, level: u8) -> Result<(), Self::Error> {
+        let contrast = (level.min(100) as u16 * 255 / 100) as u8;
+        // The lowest pre-charge period makes the dimmest levels dimmer still.
+        let precharge = if level < 20 { 1 } else { 2 };
+        self.set_brightness(Brightness::custom(precharge, contrast))
+    }
+
+    fn is_monochrome(&self) -> bool {
+        true
+    }
 }
 
 fn get_bus<SDA: InputPin + OutputPin, SCL: InputPin + OutputPin>(
@@ -56,8 +75,7 @@ fn get_bus<SDA: InputPin + OutputPin, SCL: InputPin + OutputPin>(
         sda,
         scl,
         &i2c::I2cConfig::new().baudrate(400.kHz().into()),
-    )
-    .unwrap();
+    )?;
 
     // let config = <i2c::config::MasterConfig as Default>::default().baudrate(400.kHz().into());
     // let xxx =
@@ -76,7 +94,9 @@ fn get_display(bus: Bus, address: u8) -> Result<Display> {
     )
     .into_buffered_graphics_mode();
 
-    display.init().unwrap();
+    display
+        .init()
+        .map_err(|err| anyhow!("Could not initialize display {address:#x}: {err:?}"))?;
 
     Ok(display)
 }
@@ -87,26 +107,48 @@ pub fn connect(
     i2c: i2c::I2C0,
     scl: impl InputPin + OutputPin + 'static,
     sda: impl InputPin + OutputPin + 'static,
+    tx_to_client: messages::Sender,
 ) -> Result<mpsc::Sender<DisplayCommand>> {
     let (tx, rx) = mpsc::channel();
 
-    let builder = thread::Builder::new().stack_size(8 * 1024);
+    let bus = get_bus(i2c, scl, sda)?;
 
-    builder.spawn(move || {
-        let bus = get_bus(i2c, scl, sda).unwrap();
-
-        let display0 = get_display(bus.acquire_i2c(), 0x3C).unwrap();
-        let display1 = get_display(bus.acquire_i2c(), 0x3D).unwrap();
+    let builder = thread::Builder::new()
+        .name("display".to_string())
+        .stack_size(8 * 1024);
 
-        let mut displays: [_; NUM_DISPLAYS] = [display0, display1];
+    builder.spawn(move || {
         let buttons: [_; NUM_PER_PAGE] = [
             Button::new(0, Rectangle::new(Point::new(0, 0), Size::new(128, 64))),
             Button::new(1, Rectangle::new(Point::new(0, 0), Size::new(128, 64))),
         ];
-
-        display_thread::<_, NUM_PER_PAGE, NUM_DISPLAYS>(&mut displays, &buttons, rx);
-        let x: Result<mpsc::Sender<DisplayCommand>> = Err(anyhow::anyhow!("not implemented"));
-        x
+        // There is no room to show it all the time, so the rest of the time
+        // there is a dot in the corner of the first display.
+        let status_bar = StatusBar::new(
+            0,
+            Rectangle::new(Point::new(0, 0), Size::new(128, 64)),
+            StatusStyle::Popup {
+                duration: Duration::from_secs(2),
+                indicator: Rectangle::new(Point::new(120, 0), Size::new(8, 8)),
+            },
+        );
+        let mut contents = Contents::new();
+
+        // The displays borrow the bus, so they are set up again on each
+        // restart rather than being owned by the worker.
+        supervisor::run("display", &tx_to_client, || {
+            let display0 = get_display(bus.acquire_i2c(), 0x3C)?;
+            let display1 = get_display(bus.acquire_i2c(), 0x3D)?;
+
+            let mut displays: [_; NUM_DISPLAYS] = [display0, display1];
+            display_thread::<_, NUM_PER_PAGE, NUM_DISPLAYS>(
+                &mut displays,
+                &buttons,
+                Some(&status_bar),
+                &rx,
+                &mut contents,
+            )
+        });
     })?;
 
     Ok(tx)
@@ -3,12 +3,17 @@ use super::DisplayCommand;
 use crate::boards::makerfab::ButtonInfo;
 use crate::display::graphics::display_thread;
 use crate::display::graphics::Button;
+use crate::display::graphics::Contents;
+use crate::display::graphics::StatusBar;
+use crate::display::graphics::StatusStyle;
+use crate::messages;
+use crate::supervisor;
+use anyhow::anyhow;
 use anyhow::Result;
 use display_interface_spi::SPIInterface;
 // use display_interface_spi::SPIInterfaceNoCS;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::Rectangle;
-use embedded_hal::digital::OutputPin;
 // use embedded_hal::digital::blocking::OutputPin;
 // use embedded_hal::spi::MODE_0;
 use esp_idf_hal::delay;
@@ -22,6 +27,7 @@ use esp_idf_hal::gpio::Gpio33;
 use esp_idf_hal::gpio::Gpio4;
 use esp_idf_hal::gpio::Output;
 use esp_idf_hal::gpio::PinDriver;
+use esp_idf_hal::ledc::LedcDriver;
 // use esp_idf_hal::gpio::Output;
 // use esp_idf_hal::gpio::Unknown;
 use esp_idf_hal::prelude::*;
@@ -29,6 +35,7 @@ use esp_idf_hal::spi;
 // use esp_idf_hal::spi::Master;
 use esp_idf_hal::spi::SpiDeviceDriver;
 use esp_idf_hal::spi::SpiDriver;
+use esp_idf_sys::EspError;
 // use esp_idf_hal::spi::SPI2;
 use log::info;
 use mipidsi::models::ILI9486Rgb666;
@@ -36,7 +43,6 @@ use mipidsi::Builder;
 use mipidsi::ColorOrder;
 use mipidsi::Orientation;
 use std::sync::mpsc;
-use std::thread;
 
 pub const NUM_PER_PAGE: usize = 12;
 pub const NUM_DISPLAYS: usize = 1;
@@ -50,52 +56,108 @@ type SpiInterface<'a> = SPIInterface<
 type OrigDisplay<'a> =
     mipidsi::Display<SpiInterface<'a>, ILI9486Rgb666, PinDriver<'a, Gpio4, Output>>;
 
-struct Display<'a, BL>(OrigDisplay<'a>, BL);
+/// The backlight is dimmed with PWM.
+pub struct Backlight {
+    driver: LedcDriver<'static>,
+    on: bool,
+    /// As a percentage.
+    brightness: u8,
+}
+
+impl Backlight {
+    pub fn new(driver: LedcDriver<'static>) -> Self {
+        Backlight {
+            driver,
+            on: false,
+            brightness: 100,
+        }
+    }
+
+    fn update(&mut self) -> Result<(), EspError> {
+        let duty = if self.on {
+            self.driver.get_max_duty() * self.brightness.min(100) as u32 / 100
+        } else {
+            0
+        };
+        self.driver.set_duty(duty)
+    }
+}
 
-impl<'a, BL> OriginDimensions for Display<'a, BL> {
+/// The same for every lifetime of the display.
+type ScreenError = <OrigDisplay<'static> as DrawTarget>::Error;
+
+/// Errors from either the screen or its backlight.
+#[derive(Debug)]
+enum Error {
+    Screen(ScreenError),
+    Backlight(EspError),
+}
+
+impl From<ScreenError> for Error {
+    fn from(err: ScreenError) -> Self {
+        Error::Screen(err)
+    }
+}
+
+impl From<EspError> for Error {
+    fn from(err: EspError) -> Self {
+        Error::Backlight(err)
+    }
+}
+
+struct Display<'a>(OrigDisplay<'a>, Backlight);
+
+impl<'a> OriginDimensions for Display<'a> {
     fn size(&self) -> Size {
         self.0.size()
     }
 }
 
-impl<'a, BL> DrawTarget for Display<'a, BL> {
+impl<'a> DrawTarget for Display<'a> {
     type Color = <OrigDisplay<'a> as DrawTarget>::Color;
-    type Error = <OrigDisplay<'a> as DrawTarget>::Error;
+    type Error = Error;
 
     fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
     where
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
-        self.0.draw_iter(pixels)
+        self.0.draw_iter(pixels)?;
+        Ok(())
     }
 
     fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
     where
         I: IntoIterator<Item = Self::Color>,
     {
-        self.0.fill_contiguous(area, colors)
+        self.0.fill_contiguous(area, colors)?;
+        Ok(())
     }
 
     fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
-        self.0.fill_solid(area, color)
+        self.0.fill_solid(area, color)?;
+        Ok(())
     }
 
     fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
-        self.0.clear(color)
+        self.0.clear(color)?;
+        Ok(())
     }
 }
 
-impl<'a, BL: OutputPin> FlushableDrawTarget for Display<'a, BL> {
+impl<'a> FlushableDrawTarget for Display<'a> {
     fn flush(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
 
     fn set_display_on(&mut self, on: bool) -> Result<(), Self::Error> {
-        if on {
-            self.1.set_high().unwrap();
-        } else {
-            self.1.set_low().unwrap();
-        }
+        self.1.on = on;
+        self.1.update()?;
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, level: u8) -> Result<(), Self::Error> {
+        self.1.brightness = level;
+        self.1.update()?;
         Ok(())
     }
 }
@@ -109,8 +171,9 @@ pub fn connect(
     sdo: gpio::Gpio13,
     _sdi: gpio::Gpio12,
     cs: gpio::Gpio15,
-    bl: impl OutputPin + Send + 'static,
+    bl: Backlight,
     buttons: &[ButtonInfo; NUM_PER_PAGE],
+    tx_to_client: messages::Sender,
 ) -> Result<mpsc::Sender<DisplayCommand>> {
     let (tx, rx) = mpsc::channel();
 
@@ -160,7 +223,7 @@ pub fn connect(
         .with_orientation(Orientation::Landscape(false))
         .with_color_order(ColorOrder::Bgr)
         .init(&mut delay::Ets, Some(reset))
-        .unwrap();
+        .map_err(|err| anyhow!("Could not initialize display: {err:?}"))?;
 
     let display = Display(display, bl);
 
@@ -182,11 +245,24 @@ pub fn connect(
         Button::new(0, buttons[11].position),
     ];
 
-    let builder = thread::Builder::new().stack_size(8 * 1024);
-    builder.spawn(move || {
-        let mut displays: [_; NUM_DISPLAYS] = [display];
+    // Below the bottom row of buttons.
+    let status_bar = StatusBar::new(
+        0,
+        Rectangle::new(Point::new(10, 298), Size::new(460, 20)),
+        StatusStyle::Bar,
+    );
+
+    let mut displays: [_; NUM_DISPLAYS] = [display];
+    let mut contents = Contents::new();
 
-        display_thread::<_, NUM_PER_PAGE, NUM_DISPLAYS>(&mut displays, &buttons, rx);
+    supervisor::spawn("display", Some(8 * 1024), tx_to_client, move || {
+        display_thread::<_, NUM_PER_PAGE, NUM_DISPLAYS>(
+            &mut displays,
+            &buttons,
+            Some(&status_bar),
+            &rx,
+            &mut contents,
+        )
     })?;
 
     Ok(tx)
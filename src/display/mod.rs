@@ -1,6 +1,8 @@
 use robotica_common::controllers::DisplayState;
 
 use self::icon::Icon;
+use crate::config::DisplayConfig;
+use crate::mqtt::ConnectionState;
 
 #[cfg(feature = "lca2021_badge")]
 pub mod lca2021_badge;
@@ -11,22 +13,45 @@ pub mod makerfab;
 #[cfg(feature = "robotica")]
 pub mod robotica;
 
-#[cfg(feature = "lca2021_badge")]
-pub mod graphics;
+#[cfg(feature = "simulator")]
+pub mod simulator;
 
-#[cfg(feature = "makerfab")]
+#[cfg(any(feature = "lca2021_badge", feature = "makerfab", feature = "simulator"))]
 pub mod graphics;
 
+#[cfg(any(feature = "lca2021_badge", feature = "makerfab", feature = "simulator"))]
+pub mod label;
+
+#[cfg(any(feature = "lca2021_badge", feature = "makerfab", feature = "simulator"))]
+pub mod theme;
+
 pub mod icon;
 
-#[derive(Debug)]
+/// What the status bar shows that the state machine does not know about.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Status {
+    /// Local time as `HH:MM`, if the clock has been set.
+    pub time: Option<String>,
+    /// WiFi signal strength in dBm, if connected.
+    pub rssi: Option<i8>,
+}
+
+#[derive(Debug, PartialEq)]
 pub enum DisplayCommand {
+    Settings(DisplayConfig),
     Started,
+    Error(String),
     DisplayState(DisplayState, Icon, usize, String),
     DisplayNone(usize),
     BlankAll,
     UnBlankAll,
-    ShowPage(usize),
+    /// Brightness while not blanked, as a percentage.
+    SetBrightness(u8),
+    /// Page number, number of pages and title.
+    ShowPage(usize, usize, String),
     ButtonPressed(usize),
     ButtonReleased(usize),
+    ButtonQueued(usize, bool),
+    Connection(ConnectionState),
+    Status(Status),
 }
@@ -1,6 +1,6 @@
 use std::sync::mpsc;
-use std::thread;
 
+use anyhow::anyhow;
 use anyhow::Result;
 use robotica_common::controllers::DisplayState;
 use smart_leds::RGB;
@@ -8,78 +8,197 @@ use smart_leds_trait::SmartLedsWrite;
 use ws2812_esp32_rmt_driver::Ws2812Esp32Rmt;
 
 use super::DisplayCommand;
+use crate::messages;
+use crate::mqtt::ConnectionState;
+use crate::supervisor;
 
-fn display_thread(mut leds: Ws2812Esp32Rmt, rx: mpsc::Receiver<DisplayCommand>) {
-    let color = RGB::from((1, 1, 1));
+fn get_leds(id: usize) -> Option<[usize; 4]> {
+    match id {
+        2 => Some([14, 15, 0, 1]),
+        0 => Some([2, 3, 4, 5]),
+        1 => Some([6, 7, 8, 9]),
+        3 => Some([10, 11, 12, 13]),
+        _ => None,
+    }
+}
+
+/// Pixels to show, with every second LED lit in `warning` if the connection is not up.
+fn shown_pixels(pixels: &[RGB<u8>; 16], warning: Option<RGB<u8>>) -> [RGB<u8>; 16] {
+    match warning {
+        Some(warning) => {
+            let mut pixels = [RGB::from((0, 0, 0)); 16];
+            for pixel in pixels.iter_mut().step_by(2) {
+                *pixel = warning;
+            }
+            pixels
+        }
+        None => *pixels,
+    }
+}
+
+/// What the LEDs should be showing, kept across restarts of the display
+/// thread.
+struct Contents {
+    blank: bool,
+    /// As a percentage.
+    brightness: u8,
+    pixels: [RGB<u8>; 16],
+    // Overrides the buttons while the MQTT connection is not up.
+    warning: Option<RGB<u8>>,
+    // The state colour of each button, for when it is no longer queued.
+    colors: [RGB<u8>; 4],
+}
+
+impl Contents {
+    fn new() -> Self {
+        let color = RGB::from((255, 255, 255));
+        Contents {
+            blank: false,
+            brightness: 100,
+            pixels: [color; 16],
+            warning: Some(RGB::from((255, 255, 0))),
+            colors: [color; 4],
+        }
+    }
+}
+
+/// Scale a colour to a brightness, keeping every lit channel at least 1 so
+/// that dim colours stay lit.
+fn scale(color: RGB<u8>, brightness: u8) -> RGB<u8> {
+    let level = brightness.min(100) as u16;
+    let scale = |c: u8| match (c as u16 * level + 50) / 100 {
+        0 if c > 0 && level > 0 => 1,
+        scaled => scaled as u8,
+    };
+    RGB::new(scale(color.r), scale(color.g), scale(color.b))
+}
+
+fn write(leds: &mut Ws2812Esp32Rmt, pixels: [RGB<u8>; 16], brightness: u8) -> Result<()> {
+    leds.write(pixels.into_iter().map(|pixel| scale(pixel, brightness)))
+        .map_err(|err| anyhow!("Could not write LEDs: {err:?}"))
+}
+
+fn display_thread(
+    leds: &mut Ws2812Esp32Rmt,
+    rx: &mpsc::Receiver<DisplayCommand>,
+    contents: &mut Contents,
+) -> Result<()> {
     let blank_color = RGB::from((0, 0, 0));
+    let queued_color = RGB::from((255, 255, 0));
 
-    let mut blank = false;
-    let mut pixels: [RGB<u8>; 16] = [color; 16];
     let blank_pixels: [RGB<u8>; 16] = [blank_color; 16];
 
-    let iter = pixels.iter().copied();
-    leds.write(iter).unwrap();
+    let brightness = contents.brightness;
+    if contents.blank {
+        write(leds, blank_pixels, brightness)?;
+    } else {
+        write(
+            leds,
+            shown_pixels(&contents.pixels, contents.warning),
+            brightness,
+        )?;
+    }
+
+    for received in rx.iter() {
+        let Contents {
+            blank,
+            brightness,
+            pixels,
+            warning,
+            colors,
+        } = &mut *contents;
 
-    for received in rx {
         match received {
             DisplayCommand::DisplayState(state, _icon, id, _name) => {
-                let list_leds_or_none = match id {
-                    2 => Some([14, 15, 0, 1]),
-                    0 => Some([2, 3, 4, 5]),
-                    1 => Some([6, 7, 8, 9]),
-                    3 => Some([10, 11, 12, 13]),
-                    _ => None,
-                };
+                let list_leds_or_none = get_leds(id);
 
                 let color = match state {
                     DisplayState::HardOff => (0, 0, 0),
-                    DisplayState::Error => (1, 0, 0),
-                    DisplayState::Unknown => (1, 0, 0),
-                    DisplayState::On => (0, 1, 0),
-                    DisplayState::Off => (0, 0, 1),
-                    DisplayState::AutoOff => (0, 1, 1),
+                    DisplayState::Error => (255, 0, 0),
+                    DisplayState::Unknown => (255, 0, 0),
+                    DisplayState::On => (0, 255, 0),
+                    DisplayState::Off => (0, 0, 255),
+                    DisplayState::AutoOff => (0, 255, 255),
                 };
 
                 let color = RGB::from(color);
 
                 if let Some(list_leds) = list_leds_or_none {
+                    colors[id] = color;
                     for i in list_leds {
                         pixels[i] = color;
                     }
 
-                    if !blank {
-                        let iter = pixels.iter().copied();
-                        leds.write(iter).unwrap();
+                    if !*blank {
+                        write(leds, shown_pixels(pixels, *warning), *brightness)?;
                     }
                 }
             }
             DisplayCommand::BlankAll => {
-                blank = true;
-                let iter = blank_pixels.iter().copied();
-                leds.write(iter).unwrap();
+                *blank = true;
+                write(leds, blank_pixels, *brightness)?;
+            }
+            DisplayCommand::SetBrightness(level) => {
+                *brightness = level;
+                if !*blank {
+                    write(leds, shown_pixels(pixels, *warning), *brightness)?;
+                }
             }
             DisplayCommand::UnBlankAll => {
-                blank = false;
-                let iter = pixels.iter().copied();
-                leds.write(iter).unwrap();
+                *blank = false;
+                write(leds, shown_pixels(pixels, *warning), *brightness)?;
             }
             DisplayCommand::ButtonPressed(_id) => {}
             DisplayCommand::ButtonReleased(_id) => {}
+            DisplayCommand::ButtonQueued(id, queued) => {
+                if let Some(list_leds) = get_leds(id) {
+                    let color = if queued { queued_color } else { colors[id] };
+                    for i in list_leds {
+                        pixels[i] = color;
+                    }
+
+                    if !*blank {
+                        write(leds, shown_pixels(pixels, *warning), *brightness)?;
+                    }
+                }
+            }
+            DisplayCommand::Settings(_) => {}
             DisplayCommand::Started => {}
+            DisplayCommand::Error(_) => {
+                let error_pixels: [RGB<u8>; 16] = [RGB::from((255, 0, 0)); 16];
+                write(leds, error_pixels, *brightness)?;
+            }
             DisplayCommand::DisplayNone(_) => {}
-            DisplayCommand::ShowPage(_) => {}
+            DisplayCommand::ShowPage(_, _, _) => {}
+            DisplayCommand::Status(_) => {}
+            DisplayCommand::Connection(connection) => {
+                *warning = match connection {
+                    ConnectionState::Connected => None,
+                    ConnectionState::Connecting => Some(RGB::from((255, 255, 0))),
+                    ConnectionState::Reconnecting => Some(RGB::from((255, 0, 255))),
+                    ConnectionState::AuthFailed => Some(RGB::from((255, 0, 0))),
+                };
+
+                if !*blank {
+                    write(leds, shown_pixels(pixels, *warning), *brightness)?;
+                }
+            }
         }
     }
+
+    Ok(())
 }
 
-pub fn connect(pin: u32) -> Result<mpsc::Sender<DisplayCommand>> {
-    let leds = Ws2812Esp32Rmt::new(0, pin).unwrap();
+pub fn connect(pin: u32, tx_to_client: messages::Sender) -> Result<mpsc::Sender<DisplayCommand>> {
+    let mut leds =
+        Ws2812Esp32Rmt::new(0, pin).map_err(|err| anyhow!("Could not set up LEDs: {err:?}"))?;
 
     let (tx, rx) = mpsc::channel();
+    let mut contents = Contents::new();
 
-    thread::spawn(move || {
-        display_thread(leds, rx);
-    });
+    supervisor::spawn("display", None, tx_to_client, move || {
+        display_thread(&mut leds, &rx, &mut contents)
+    })?;
 
     Ok(tx)
 }
@@ -0,0 +1,175 @@
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+use super::graphics::display_thread;
+use super::graphics::Button;
+use super::graphics::Contents;
+use super::graphics::FlushableDrawTarget;
+use super::graphics::StatusBar;
+use super::graphics::StatusStyle;
+use super::DisplayCommand;
+use crate::messages;
+use crate::supervisor;
+
+pub const NUM_PER_PAGE: usize = 12;
+pub const NUM_DISPLAYS: usize = 1;
+
+// Same geometry as the makerfab screen, so layouts can be checked on the host.
+const WIDTH: u32 = 480;
+const HEIGHT: u32 = 320;
+const NUM_COLUMNS: usize = 3;
+
+/// Contents of the simulated screen as of the last flush.
+pub struct Screen {
+    size: Size,
+    pixels: Vec<Rgb888>,
+    on: bool,
+    /// As a percentage.
+    brightness: u8,
+}
+
+pub type SharedScreen = Arc<Mutex<Screen>>;
+
+impl Screen {
+    fn new(size: Size) -> Self {
+        let len = (size.width * size.height) as usize;
+        Screen {
+            size,
+            pixels: vec![Rgb888::BLACK; len],
+            on: false,
+            brightness: 100,
+        }
+    }
+
+    pub fn write_png(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.size.width, self.size.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let scale = |c: u8| (c as u16 * self.brightness.min(100) as u16 / 100) as u8;
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|c| match self.on {
+                true => [scale(c.r()), scale(c.g()), scale(c.b())],
+                false => [0, 0, 0],
+            })
+            .collect();
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        Ok(())
+    }
+}
+
+struct FrameBuffer {
+    back: Screen,
+    front: SharedScreen,
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        self.back.size
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let size = self.back.size;
+        for Pixel(point, color) in pixels {
+            if let (Ok(x), Ok(y)) = (u32::try_from(point.x), u32::try_from(point.y)) {
+                if x < size.width && y < size.height {
+                    self.back.pixels[(y * size.width + x) as usize] = color;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FlushableDrawTarget for FrameBuffer {
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        let mut front = self.front.lock().unwrap();
+        front.pixels.copy_from_slice(&self.back.pixels);
+        Ok(())
+    }
+
+    fn set_display_on(&mut self, on: bool) -> Result<(), Self::Error> {
+        self.back.on = on;
+        self.front.lock().unwrap().on = on;
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, level: u8) -> Result<(), Self::Error> {
+        self.back.brightness = level;
+        self.front.lock().unwrap().brightness = level;
+        Ok(())
+    }
+}
+
+pub fn button_position(id: usize) -> Rectangle {
+    let column = (id % NUM_COLUMNS) as i32;
+    let row = (id / NUM_COLUMNS) as i32;
+    Rectangle::new(
+        Point::new(10 + column * (128 + 10), 10 + row * (64 + 10)),
+        Size::new(128, 64),
+    )
+}
+
+/// Below the bottom row of buttons.
+fn status_bar_position() -> Rectangle {
+    let rows = (NUM_PER_PAGE / NUM_COLUMNS) as i32;
+    Rectangle::new(
+        Point::new(10, 10 + rows * (64 + 10) - 8),
+        Size::new(WIDTH - 20, 20),
+    )
+}
+
+pub fn connect(
+    tx_to_client: messages::Sender,
+) -> Result<(mpsc::Sender<DisplayCommand>, SharedScreen)> {
+    let (tx, rx) = mpsc::channel();
+
+    let size = Size::new(WIDTH, HEIGHT);
+    let screen = Arc::new(Mutex::new(Screen::new(size)));
+    let display = FrameBuffer {
+        back: Screen::new(size),
+        front: screen.clone(),
+    };
+
+    let buttons: [_; NUM_PER_PAGE] = std::array::from_fn(|id| Button::new(0, button_position(id)));
+    let status_bar = StatusBar::new(0, status_bar_position(), StatusStyle::Bar);
+
+    let mut displays: [_; NUM_DISPLAYS] = [display];
+    let mut contents = Contents::new();
+
+    supervisor::spawn("display", None, tx_to_client, move || {
+        display_thread::<_, NUM_PER_PAGE, NUM_DISPLAYS>(
+            &mut displays,
+            &buttons,
+            Some(&status_bar),
+            &rx,
+            &mut contents,
+        )
+    })?;
+
+    Ok((tx, screen))
+}
@@ -0,0 +1,176 @@
+use embedded_graphics::pixelcolor::Rgb555;
+use embedded_graphics::prelude::RgbColor;
+use robotica_common::controllers::DisplayState;
+
+use crate::config::ThemeName;
+
+/// Colour of the strip down the side of a tile, for each state of its
+/// controller. The background colour leaves it out.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Accents {
+    pub on: Rgb555,
+    pub off: Rgb555,
+    pub auto_off: Rgb555,
+    pub hard_off: Rgb555,
+    pub error: Rgb555,
+    pub unknown: Rgb555,
+}
+
+/// Colours used by the graphical displays.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Theme {
+    pub background: Rgb555,
+    /// Controller names and the text of the status bar.
+    pub label: Rgb555,
+    /// The greyscale icons are drawn in shades of this.
+    pub icon: Rgb555,
+    /// Outline of a tile while its button is held.
+    pub pressed: Rgb555,
+    pub queued: Rgb555,
+    /// Outline and text of the box over a tile in error.
+    pub error_box: Rgb555,
+    /// Shown on every display until the first page is drawn.
+    pub loading_fill: Rgb555,
+    pub loading_outline: Rgb555,
+    pub loading_text: Rgb555,
+    /// Background of the screen shown for a fatal error.
+    pub error_screen: Rgb555,
+    /// What is left between the tiles once started.
+    pub started: Rgb555,
+    pub accents: Accents,
+}
+
+impl Theme {
+    pub fn new(name: ThemeName) -> Theme {
+        match name {
+            ThemeName::Default => Theme::standard(),
+            ThemeName::Night => Theme::night(),
+            ThemeName::HighContrast => Theme::high_contrast(),
+        }
+    }
+
+    /// White on black, as it has always looked.
+    fn standard() -> Theme {
+        Theme {
+            background: Rgb555::BLACK,
+            label: Rgb555::WHITE,
+            icon: Rgb555::WHITE,
+            pressed: Rgb555::GREEN,
+            queued: Rgb555::YELLOW,
+            error_box: Rgb555::WHITE,
+            loading_fill: Rgb555::RED,
+            loading_outline: Rgb555::YELLOW,
+            loading_text: Rgb555::WHITE,
+            error_screen: Rgb555::RED,
+            started: Rgb555::BLUE,
+            accents: Accents {
+                on: Rgb555::BLACK,
+                off: Rgb555::BLACK,
+                auto_off: Rgb555::BLACK,
+                hard_off: Rgb555::BLACK,
+                error: Rgb555::BLACK,
+                unknown: Rgb555::BLACK,
+            },
+        }
+    }
+
+    /// Dim reds on black, to light up a dark bedroom as little as possible.
+    fn night() -> Theme {
+        let dim = Rgb555::new(12, 3, 0);
+        let faint = Rgb555::new(6, 1, 0);
+        let red = Rgb555::new(14, 0, 0);
+        Theme {
+            background: Rgb555::BLACK,
+            label: dim,
+            icon: dim,
+            pressed: red,
+            queued: dim,
+            error_box: red,
+            loading_fill: Rgb555::BLACK,
+            loading_outline: faint,
+            loading_text: dim,
+            error_screen: Rgb555::new(6, 0, 0),
+            started: Rgb555::BLACK,
+            accents: Accents {
+                on: dim,
+                off: Rgb555::BLACK,
+                auto_off: faint,
+                hard_off: Rgb555::BLACK,
+                error: red,
+                unknown: red,
+            },
+        }
+    }
+
+    /// Only black, white and fully saturated colours, with every state
+    /// marked.
+    fn high_contrast() -> Theme {
+        Theme {
+            background: Rgb555::BLACK,
+            label: Rgb555::WHITE,
+            icon: Rgb555::WHITE,
+            pressed: Rgb555::YELLOW,
+            queued: Rgb555::YELLOW,
+            error_box: Rgb555::WHITE,
+            loading_fill: Rgb555::BLACK,
+            loading_outline: Rgb555::WHITE,
+            loading_text: Rgb555::WHITE,
+            error_screen: Rgb555::RED,
+            started: Rgb555::BLACK,
+            accents: Accents {
+                on: Rgb555::GREEN,
+                off: Rgb555::WHITE,
+                auto_off: Rgb555::CYAN,
+                hard_off: Rgb555::BLACK,
+                error: Rgb555::RED,
+                unknown: Rgb555::MAGENTA,
+            },
+        }
+    }
+
+    /// This theme for displays that can only turn pixels on or off, where
+    /// dim colours would vanish and bright ones would all look the same.
+    /// Everything is drawn white on black, and the accent is only kept for
+    /// controllers that are on.
+    pub fn monochrome(&self) -> Theme {
+        let on = Rgb555::WHITE;
+        let off = Rgb555::BLACK;
+        let accent_on = if self.accents.on == self.background {
+            off
+        } else {
+            on
+        };
+        Theme {
+            background: off,
+            label: on,
+            icon: on,
+            pressed: on,
+            queued: on,
+            error_box: on,
+            loading_fill: off,
+            loading_outline: on,
+            loading_text: on,
+            error_screen: off,
+            started: off,
+            accents: Accents {
+                on: accent_on,
+                off,
+                auto_off: off,
+                hard_off: off,
+                error: off,
+                unknown: off,
+            },
+        }
+    }
+
+    pub fn get_accent(&self, state: &DisplayState) -> Rgb555 {
+        match state {
+            DisplayState::On => self.accents.on,
+            DisplayState::Off => self.accents.off,
+            DisplayState::AutoOff => self.accents.auto_off,
+            DisplayState::HardOff => self.accents.hard_off,
+            DisplayState::Error => self.accents.error,
+            DisplayState::Unknown => self.accents.unknown,
+        }
+    }
+}
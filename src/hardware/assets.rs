@@ -0,0 +1,39 @@
+use std::ffi::c_void;
+
+use anyhow::bail;
+use anyhow::Result;
+use esp_idf_sys::esp;
+
+/// Subtype of the `assets` data partition in `partitions.csv`.
+const ASSETS_SUBTYPE: esp_idf_sys::esp_partition_subtype_t = 0x40;
+
+/// Map the `assets` partition into memory. It is never unmapped, so the data
+/// lives as long as the app.
+pub fn map_assets() -> Result<&'static [u8]> {
+    let partition = unsafe {
+        esp_idf_sys::esp_partition_find_first(
+            esp_idf_sys::esp_partition_type_t_ESP_PARTITION_TYPE_DATA,
+            ASSETS_SUBTYPE,
+            b"assets\0".as_ptr() as *const _,
+        )
+    };
+    if partition.is_null() {
+        bail!("No assets partition");
+    }
+
+    let size = unsafe { (*partition).size } as usize;
+    let mut ptr: *const c_void = std::ptr::null();
+    let mut handle: esp_idf_sys::spi_flash_mmap_handle_t = 0;
+    esp!(unsafe {
+        esp_idf_sys::esp_partition_mmap(
+            partition,
+            0,
+            size,
+            esp_idf_sys::spi_flash_mmap_memory_t_SPI_FLASH_MMAP_DATA,
+            &mut ptr,
+            &mut handle,
+        )
+    })?;
+
+    Ok(unsafe { std::slice::from_raw_parts(ptr as *const u8, size) })
+}
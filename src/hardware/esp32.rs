@@ -1,4 +1,18 @@
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::nvs::EspNvs;
+use esp_idf_svc::nvs::NvsDefault;
+use esp_idf_svc::timer::EspTimer;
+use esp_idf_svc::timer::EspTimerService;
+use esp_idf_svc::timer::Task;
 use esp_idf_sys::esp_efuse_mac_get_default;
+use esp_idf_sys::esp_get_free_heap_size;
+use esp_idf_sys::esp_get_minimum_free_heap_size;
+use esp_idf_sys::esp_reset_reason;
+use esp_idf_sys::esp_restart;
+
+pub type TimerService = EspTimerService<Task>;
+pub type Timer = EspTimer;
 
 pub fn get_unique_id() -> String {
     let mut mac: [u8; 6] = [0; 6];
@@ -8,3 +22,89 @@ pub fn get_unique_id() -> String {
     }
     hex::encode(mac)
 }
+
+pub fn restart() {
+    unsafe {
+        esp_restart();
+    }
+}
+
+pub fn get_free_heap() -> Option<u32> {
+    Some(unsafe { esp_get_free_heap_size() })
+}
+
+pub fn get_min_free_heap() -> Option<u32> {
+    Some(unsafe { esp_get_minimum_free_heap_size() })
+}
+
+pub fn get_reset_reason() -> Option<&'static str> {
+    let reason = match unsafe { esp_reset_reason() } {
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_POWERON => "power_on",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_EXT => "external",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_SW => "software",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_PANIC => "panic",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_INT_WDT => "interrupt_watchdog",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_TASK_WDT => "task_watchdog",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_WDT => "watchdog",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP => "deep_sleep",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_BROWNOUT => "brownout",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_SDIO => "sdio",
+        _ => "unknown",
+    };
+    Some(reason)
+}
+
+/// Set the POSIX TZ string used for the local time, for example
+/// `AEST-10AEDT,M10.1.0,M4.1.0/3`.
+pub fn set_timezone(timezone: &str) {
+    std::env::set_var("TZ", timezone);
+    unsafe {
+        esp_idf_sys::tzset();
+    }
+}
+
+/// Hour and minute of the local time, or None if SNTP has not set the
+/// clock yet.
+pub fn get_local_time() -> Option<(u8, u8)> {
+    let mut now: esp_idf_sys::time_t = 0;
+    let mut tm: esp_idf_sys::tm = unsafe { std::mem::zeroed() };
+    unsafe {
+        esp_idf_sys::time(&mut now);
+        esp_idf_sys::localtime_r(&now, &mut tm);
+    }
+
+    // The clock starts from 1970 on boot.
+    if tm.tm_year + 1900 < 2023 {
+        return None;
+    }
+    Some((tm.tm_hour as u8, tm.tm_min as u8))
+}
+
+const NVS_NAMESPACE: &str = "robotica";
+
+/// Settings that survive a reboot, kept in the default NVS partition.
+pub struct Storage(EspNvs<NvsDefault>);
+
+impl Storage {
+    pub fn new() -> Result<Self> {
+        let partition = EspDefaultNvsPartition::take()?;
+        let nvs = EspNvs::new(partition, NVS_NAMESPACE, true)?;
+        Ok(Storage(nvs))
+    }
+
+    pub fn load(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let len = match self.0.len(key)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        let mut buf = vec![0; len];
+        let data = self.0.get_raw(key, &mut buf)?.map(|data| data.to_vec());
+        Ok(data)
+    }
+
+    pub fn store(&mut self, key: &str, data: &[u8]) -> Result<()> {
+        self.0.set_raw(key, data)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use log::*;
+
+use robotica_remote::pack;
+
+pub fn get_unique_id() -> String {
+    env::var("SIMULATOR_ID").unwrap_or_else(|_| "simulator".to_string())
+}
+
+/// There is nothing to restart the simulator, so just stop.
+pub fn restart() {
+    info!("Exiting instead of restarting");
+    std::process::exit(0);
+}
+
+pub fn get_free_heap() -> Option<u32> {
+    None
+}
+
+pub fn get_min_free_heap() -> Option<u32> {
+    None
+}
+
+pub fn get_reset_reason() -> Option<&'static str> {
+    None
+}
+
+/// The simulator always shows UTC.
+pub fn set_timezone(timezone: &str) {
+    debug!("Ignoring timezone {timezone}");
+}
+
+pub fn get_local_time() -> Option<(u8, u8)> {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()?;
+    let minutes = now.as_secs() / 60 % (24 * 60);
+    Some(((minutes / 60) as u8, (minutes % 60) as u8))
+}
+
+/// The icon pack saved as `assets` in storage if there is one, or else one
+/// built from `src/display/images`.
+pub fn map_assets() -> Result<&'static [u8]> {
+    let data = match Storage::new()?.load("assets")? {
+        Some(data) => data,
+        None => {
+            let images = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/display/images");
+            pack::build_from_dir(&images)?
+        }
+    };
+    Ok(Box::leak(data.into_boxed_slice()))
+}
+
+/// Settings that survive a restart, kept as files in the directory named by
+/// `SIMULATOR_STORAGE`.
+pub struct Storage(PathBuf);
+
+impl Storage {
+    pub fn new() -> Result<Self> {
+        let path = env::var("SIMULATOR_STORAGE").unwrap_or_else(|_| "simulator".to_string());
+        Ok(Storage(PathBuf::from(path)))
+    }
+
+    pub fn load(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.0.join(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn store(&mut self, key: &str, data: &[u8]) -> Result<()> {
+        fs::create_dir_all(&self.0)?;
+        fs::write(self.0.join(key), data)?;
+        Ok(())
+    }
+}
+
+enum TimerCommand {
+    After(Duration),
+    Every(Duration),
+    Cancel,
+}
+
+/// Thread based stand in for `EspTimerService` when running on the host.
+pub struct TimerService;
+
+impl TimerService {
+    pub fn new() -> Result<Self> {
+        Ok(TimerService)
+    }
+
+    pub fn timer<F: FnMut() + Send + 'static>(&self, mut callback: F) -> Result<Timer> {
+        let (tx, rx) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("timer".to_string())
+            .spawn(move || {
+                let mut deadline: Option<Instant> = None;
+                let mut period: Option<Duration> = None;
+                loop {
+                    let command = match deadline {
+                        Some(deadline) => {
+                            let timeout = deadline.saturating_duration_since(Instant::now());
+                            match rx.recv_timeout(timeout) {
+                                Ok(command) => Some(command),
+                                Err(RecvTimeoutError::Timeout) => None,
+                                Err(RecvTimeoutError::Disconnected) => break,
+                            }
+                        }
+                        None => match rx.recv() {
+                            Ok(command) => Some(command),
+                            Err(_) => break,
+                        },
+                    };
+
+                    match command {
+                        Some(TimerCommand::After(duration)) => {
+                            deadline = Some(Instant::now() + duration);
+                            period = None;
+                        }
+                        Some(TimerCommand::Every(duration)) => {
+                            deadline = Some(Instant::now() + duration);
+                            period = Some(duration);
+                        }
+                        Some(TimerCommand::Cancel) => {
+                            deadline = None;
+                            period = None;
+                        }
+                        None => {
+                            deadline = period.map(|period| Instant::now() + period);
+                            callback();
+                        }
+                    }
+                }
+            })?;
+
+        Ok(Timer { tx })
+    }
+}
+
+pub struct Timer {
+    tx: mpsc::Sender<TimerCommand>,
+}
+
+impl Timer {
+    pub fn after(&mut self, duration: Duration) -> Result<()> {
+        self.tx.send(TimerCommand::After(duration))?;
+        Ok(())
+    }
+
+    pub fn every(&mut self, duration: Duration) -> Result<()> {
+        self.tx.send(TimerCommand::Every(duration))?;
+        Ok(())
+    }
+
+    pub fn cancel(&mut self) -> Result<bool> {
+        self.tx.send(TimerCommand::Cancel)?;
+        Ok(true)
+    }
+}
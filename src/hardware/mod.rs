@@ -1 +1,30 @@
+#[cfg(target_os = "espidf")]
 pub mod esp32;
+
+#[cfg(target_os = "espidf")]
+pub use esp32::{
+    get_free_heap, get_local_time, get_min_free_heap, get_reset_reason, get_unique_id, restart,
+    set_timezone, Storage, Timer, TimerService,
+};
+
+/// Only the boards with a screen draw icons.
+#[cfg(all(
+    target_os = "espidf",
+    any(feature = "lca2021_badge", feature = "makerfab")
+))]
+mod assets;
+
+#[cfg(all(
+    target_os = "espidf",
+    any(feature = "lca2021_badge", feature = "makerfab")
+))]
+pub use assets::map_assets;
+
+#[cfg(not(target_os = "espidf"))]
+pub mod host;
+
+#[cfg(not(target_os = "espidf"))]
+pub use host::{
+    get_free_heap, get_local_time, get_min_free_heap, get_reset_reason, get_unique_id, map_assets,
+    restart, set_timezone, Storage, Timer, TimerService,
+};
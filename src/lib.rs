@@ -0,0 +1,3 @@
+//! Code shared between the firmware and the host tools in `src/bin`.
+
+pub mod pack;
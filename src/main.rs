@@ -1,213 +1,161 @@
 #![allow(clippy::single_component_path_imports)]
 
 use std::env;
-use std::ops::Range;
 use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::Result;
 use boards::Board;
 use boards::NUM_CONTROLLERS_PER_PAGE;
-use config::Controller;
+#[cfg(target_os = "espidf")]
 use embedded_svc::timer::OnceTimer;
+#[cfg(target_os = "espidf")]
+use embedded_svc::timer::PeriodicTimer;
+#[cfg(target_os = "espidf")]
 use embedded_svc::timer::Timer;
-use esp_idf_svc::timer::EspTimer;
-use esp_idf_svc::timer::EspTimerService;
+use hardware::get_unique_id;
+use hardware::TimerService;
 use log::*;
 
 mod button;
-use button::ButtonId;
-use mqtt::Subscriptions;
 use pretty_env_logger::env_logger::WriteStyle;
-use robotica_common::controllers::DisplayState;
+use state::Effect;
+use state::RemoteState;
+use telemetry::Telemetry;
 
 mod display;
 use crate::display::DisplayCommand;
 use crate::messages::Message;
 
 mod boards;
+mod command;
 mod config;
+mod discovery;
 
 mod hardware;
+#[cfg(target_os = "espidf")]
 mod input;
 mod messages;
 mod mqtt;
+mod state;
+mod supervisor;
+mod telemetry;
+#[cfg(target_os = "espidf")]
 mod wifi;
 
+#[cfg(target_os = "espidf")]
 const MQTT_URL: &str = env!("MQTT_URL");
 
+/// How often to update the clock on the status bar.
+const CLOCK_INTERVAL: Duration = Duration::from_secs(15);
+
 #[cfg(esp32s2)]
 include!(env!("EMBUILD_GENERATED_SYMBOLS_FILE"));
 
 #[cfg(esp32s2)]
 const ULP: &[u8] = include_bytes!(env!("EMBUILD_GENERATED_BIN_FILE"));
 
-enum TimeOfDay {
-    Day,
-    Night,
-}
-
-struct RequestedDisplayStatus {
-    time_of_day: TimeOfDay,
-    light_on: bool,
-    night_timer: bool,
-}
-
-struct ActualDisplayStatus {
-    timer_on: bool,
-    display_on: bool,
-}
-
-impl RequestedDisplayStatus {
-    fn get_timer_required(&self) -> bool {
-        self.night_timer
-    }
-
-    fn get_display_required(&self) -> bool {
-        matches!(self.time_of_day, TimeOfDay::Day) || self.light_on || self.night_timer
-    }
-
-    fn turn_night_timer_on(&mut self) {
-        self.night_timer = true;
-    }
-
-    fn turn_night_timer_off(&mut self) {
-        self.night_timer = false;
-    }
-}
-
-fn update_display(
-    display: &mpsc::Sender<DisplayCommand>,
-    id_in_page: usize,
-    controller: &Controller,
-    state: DisplayState,
-) {
-    let name = controller.get_name().to_string();
-    let icon = controller.get_icon();
-    let message = DisplayCommand::DisplayState(state, icon, id_in_page, name);
-    display.send(message).unwrap();
+/// Everything the effects of a message act on.
+struct Outputs<B: Board> {
+    board: B,
+    display: mpsc::Sender<DisplayCommand>,
+    mqtt: mqtt::Mqtt,
+    blank_timer: hardware::Timer,
+    telemetry_timer: hardware::Timer,
+    storage: hardware::Storage,
+    started: Instant,
 }
 
-fn update_displays(
-    display: &mpsc::Sender<DisplayCommand>,
-    controllers: &[Controller],
-    page_num: usize,
-) {
-    let controllers = get_controllers_per_page(controllers, page_num);
-    for (id_in_page, controller) in controllers.iter().enumerate() {
-        if let Some(controller) = controller {
-            let state = controller.get_display_state();
-            update_display(display, id_in_page, controller, state);
-        } else {
-            let message = DisplayCommand::DisplayNone(id_in_page);
-            display.send(message).unwrap();
-        };
-    }
-}
-
-fn do_blank(
-    display: &mpsc::Sender<DisplayCommand>,
-    timer: &mut EspTimer,
-    requested_display_status: &RequestedDisplayStatus,
-    status: &mut ActualDisplayStatus,
-    force_timer_reset: bool,
-) {
-    let timer_required = requested_display_status.get_timer_required();
-    let display_required = requested_display_status.get_display_required();
-
-    match (timer_required, status.timer_on) {
-        (true, true) => {
-            if force_timer_reset {
-                info!("resetting blank timer");
-                timer.cancel().unwrap();
-                timer.after(std::time::Duration::new(10, 0)).unwrap();
-                status.timer_on = true;
+impl<B: Board> Outputs<B> {
+    fn do_effects(&mut self, effects: Vec<Effect>) {
+        for effect in effects {
+            match effect {
+                Effect::Display(command) => self.send_display(command),
+                Effect::Publish {
+                    topic,
+                    retain,
+                    payload,
+                } => self.mqtt.publish(&topic, retain, &payload),
+                Effect::StartBlankTimer(duration) => {
+                    log_error("Cancel blank timer", self.blank_timer.cancel());
+                    log_error("Start blank timer", self.blank_timer.after(duration));
+                }
+                Effect::CancelBlankTimer => {
+                    log_error("Cancel blank timer", self.blank_timer.cancel());
+                }
+                Effect::Subscribe(subscriptions) => self.mqtt.set_subscriptions(subscriptions),
+                Effect::StoreConfig(data) => {
+                    if let Err(err) = config::save_config(&mut self.storage, &data) {
+                        error!("Could not save config: {err}");
+                    }
+                }
+                Effect::Restart => {
+                    // Give the MQTT thread a chance to send the reply first.
+                    thread::sleep(Duration::from_secs(1));
+                    hardware::restart();
+                }
+                Effect::StartTelemetryTimer(duration) => {
+                    log_error("Cancel telemetry timer", self.telemetry_timer.cancel());
+                    log_error(
+                        "Start telemetry timer",
+                        self.telemetry_timer.every(duration),
+                    );
+                }
+                Effect::CancelTelemetryTimer => {
+                    log_error("Cancel telemetry timer", self.telemetry_timer.cancel());
+                }
+                Effect::PublishTelemetry {
+                    topic,
+                    mqtt_reconnects,
+                } => {
+                    let telemetry = Telemetry::collect(
+                        self.started.elapsed(),
+                        self.board.get_rssi(),
+                        mqtt_reconnects,
+                        self.mqtt.get_decode_failures(),
+                    );
+                    let payload = serde_json::to_string(&telemetry).unwrap();
+                    self.mqtt.publish(&topic, false, &payload);
+                }
+                Effect::SetTimezone(timezone) => hardware::set_timezone(&timezone),
+                Effect::UpdateStatus => {
+                    let time = hardware::get_local_time()
+                        .map(|(hour, minute)| format!("{hour:02}:{minute:02}"));
+                    let status = display::Status {
+                        time,
+                        rssi: self.board.get_rssi(),
+                    };
+                    self.send_display(DisplayCommand::Status(status));
+                }
             }
         }
-        (true, false) => {
-            info!("starting blank timer");
-            timer.cancel().unwrap();
-            timer.after(std::time::Duration::new(10, 0)).unwrap();
-            status.timer_on = true;
-        }
-        (false, true) => {
-            info!("stopping blank timer");
-            timer.cancel().unwrap();
-            status.timer_on = false;
-        }
-        (false, false) => {}
-    };
-
-    match (display_required, status.display_on) {
-        (true, false) => {
-            info!("turning display on");
-            status.display_on = true;
-            display.send(DisplayCommand::UnBlankAll).unwrap();
-        }
-        (false, true) => {
-            info!("turning display off");
-            status.display_on = false;
-            display.send(DisplayCommand::BlankAll).unwrap();
-        }
-        (true, true) => {}
-        (false, false) => {}
-    };
-}
+    }
 
-fn button_press(controllers: &mut [Controller], id: usize, mqtt: &mqtt::Mqtt) {
-    info!("Got button {} press", id);
-    let controller_or_none = controllers.get_mut(id);
-    if let Some(controller) = controller_or_none {
-        let commands = controller.get_press_commands();
-        for command in commands {
-            let topic = &command.topic;
-            let data = command.payload;
-            info!("Send {}: {}", topic, data);
-            mqtt.publish(topic, false, &data);
+    fn send_display(&self, command: DisplayCommand) {
+        if self.display.send(command).is_err() {
+            error!("Display thread has gone, dropping command");
         }
-    } else {
-        error!("Controller for button {} does not exist", id);
     }
 }
 
-fn get_controller_range_for_page(page: usize) -> Range<usize> {
-    let start = page * NUM_CONTROLLERS_PER_PAGE;
-    let end = start + NUM_CONTROLLERS_PER_PAGE;
-    Range { start, end }
-}
-
-fn get_controllers_per_page(controllers: &[Controller], page: usize) -> Vec<Option<&Controller>> {
-    let mut range = get_controller_range_for_page(page);
-    let len = controllers.len();
-
-    if range.end > len {
-        range.end = len
+fn log_error<T, E: std::fmt::Display>(what: &str, result: Result<T, E>) {
+    if let Err(err) = result {
+        error!("{what} failed: {err}");
     }
-    let controllers = &controllers[range];
-    let len = controllers.len();
-
-    let mut output: Vec<_> = controllers.iter().map(Some).collect();
-    output.extend((len..NUM_CONTROLLERS_PER_PAGE).map(|_| None));
-
-    output
-}
-
-fn controller_to_page_id(controller_id: usize) -> (usize, usize) {
-    let page_num = controller_id / NUM_CONTROLLERS_PER_PAGE;
-    let id_in_page = controller_id % NUM_CONTROLLERS_PER_PAGE;
-    (page_num, id_in_page)
 }
 
-fn page_to_controller_id(page_num: usize, id_in_page: usize) -> usize {
-    page_num * NUM_CONTROLLERS_PER_PAGE + id_in_page
-}
-
-fn get_num_pages(controllers: &[Controller]) -> usize {
-    let len = controllers.len();
-    let num = NUM_CONTROLLERS_PER_PAGE;
-    len / num + usize::from(len % num != 0)
+/// Send a message from a timer callback, which can't fail.
+fn send_message(tx: &messages::Sender, message: Message) {
+    if tx.send(message).is_err() {
+        error!("Main thread has gone, dropping timer message");
+    }
 }
 
 fn main() -> Result<()> {
+    let started = Instant::now();
+
     pretty_env_logger::formatted_timed_builder()
         .filter(None, LevelFilter::Trace)
         .write_style(WriteStyle::Always)
@@ -218,243 +166,75 @@ fn main() -> Result<()> {
     let board = boards::configure_devices(tx.clone())?;
     let display = board.get_display();
 
-    let mut controllers = config::get_controllers_config();
-
-    let subscriptions = {
-        let mut subscriptions = Subscriptions::new();
-        for (index, f) in controllers.iter().enumerate() {
-            let new_subscriptions = f.get_subscriptions();
-            for s in new_subscriptions {
-                let label = mqtt::Label::Button(index, s.label);
-                info!("Subscribing to {}.", s.topic);
-                subscriptions.add(&s.topic, label);
-            }
-        }
+    let storage = hardware::Storage::new()?;
+    let (config, config_error) = config::load_config(&storage, NUM_CONTROLLERS_PER_PAGE);
+    if let Some(error) = config_error {
+        display.send(DisplayCommand::Error(error))?;
+        thread::sleep(Duration::from_secs(10));
+    }
 
-        subscriptions.add(config::NIGHT_TOPIC, mqtt::Label::NightStatus);
-        subscriptions.add(config::LIGHT_TOPIC, mqtt::Label::LightStatus);
-        subscriptions
+    #[cfg(target_os = "espidf")]
+    let max_message_size = config.mqtt.max_message_size;
+    let mut state = RemoteState::new(config, NUM_CONTROLLERS_PER_PAGE, &get_unique_id());
+    let subscriptions = state.get_subscriptions();
+    let will = state.get_will();
+
+    #[cfg(target_os = "espidf")]
+    let mqtt = {
+        let certificates = mqtt::esp::Certificates::load(&storage)?;
+        mqtt::Mqtt::connect(
+            move |events| {
+                mqtt::esp::EspTransport::new(
+                    MQTT_URL,
+                    &certificates,
+                    Some(will.clone()),
+                    max_message_size,
+                    events,
+                )
+            },
+            tx.clone(),
+            subscriptions,
+        )?
     };
 
-    let mqtt = mqtt::Mqtt::connect(MQTT_URL, tx.clone(), subscriptions);
-
-    let timer_service = EspTimerService::new().unwrap();
-    let mut timer = timer_service
-        .timer(move || {
-            tx.send(Message::BlankDisplays).unwrap();
-        })
-        .unwrap();
+    #[cfg(not(target_os = "espidf"))]
+    let mqtt = {
+        let broker = board.get_broker();
+        mqtt::Mqtt::connect(
+            move |events| Ok(broker.connect(events, Some(will.clone()))),
+            tx.clone(),
+            subscriptions,
+        )?
+    };
 
-    let mut requested_display_status: RequestedDisplayStatus = RequestedDisplayStatus {
-        time_of_day: TimeOfDay::Day,
-        light_on: false,
-        night_timer: false,
+    let timer_service = TimerService::new()?;
+    let blank_timer = {
+        let tx = tx.clone();
+        timer_service.timer(move || send_message(&tx, Message::BlankDisplays))?
     };
-    let mut status: ActualDisplayStatus = ActualDisplayStatus {
-        display_on: true,
-        timer_on: false,
+    let telemetry_timer = {
+        let tx = tx.clone();
+        timer_service.timer(move || send_message(&tx, Message::Telemetry))?
+    };
+    let mut clock_timer = timer_service.timer(move || send_message(&tx, Message::Clock))?;
+    clock_timer.every(CLOCK_INTERVAL)?;
+
+    let mut outputs = Outputs {
+        board,
+        display,
+        mqtt,
+        blank_timer,
+        telemetry_timer,
+        storage,
+        started,
     };
 
-    do_blank(
-        &display,
-        &mut timer,
-        &requested_display_status,
-        &mut status,
-        false,
-    );
-
-    let mut page_num = 0;
-    let last_page = get_num_pages(&controllers) - 1;
-
-    display.send(DisplayCommand::Started).unwrap();
-    display.send(DisplayCommand::ShowPage(page_num)).unwrap();
-    update_displays(&display, &controllers, page_num);
+    let effects = state.start();
+    outputs.do_effects(effects);
 
     for received in rx {
-        match received {
-            Message::MqttReceived(_, power, mqtt::Label::NightStatus) => {
-                info!("Got night: {}", power);
-                match power.as_str() {
-                    "ON" => requested_display_status.time_of_day = TimeOfDay::Night,
-                    "OFF" => requested_display_status.time_of_day = TimeOfDay::Day,
-                    _ => {}
-                };
-                do_blank(
-                    &display,
-                    &mut timer,
-                    &requested_display_status,
-                    &mut status,
-                    false,
-                );
-            }
-            Message::MqttReceived(_, power, mqtt::Label::LightStatus) => {
-                info!("Got light status: {}", power);
-                match power.as_str() {
-                    "ON" => requested_display_status.light_on = true,
-                    "OFF" => requested_display_status.light_on = false,
-                    _ => {}
-                };
-                do_blank(
-                    &display,
-                    &mut timer,
-                    &requested_display_status,
-                    &mut status,
-                    false,
-                );
-            }
-            Message::MqttReceived(topic, data, mqtt::Label::Button(id, sid)) => {
-                info!("Got message for button {id}/{sid}: {} - {}", topic, data);
-                let controller = controllers.get_mut(id).unwrap();
-                let old_state = controller.get_display_state();
-                controller.process_message(sid, data);
-                let state = controller.get_display_state();
-                info!("State changed: {} - {:?} -> {:?}", topic, old_state, state);
-
-                let (msg_page_num, id_in_page) = controller_to_page_id(id);
-                if page_num == msg_page_num && old_state != state {
-                    update_display(&display, id_in_page, controller, state);
-                }
-            }
-            Message::MqttConnect => {
-                info!("Got connected");
-            }
-            Message::MqttDisconnect => {
-                info!("Got disconnected");
-                for controller in controllers.iter_mut() {
-                    controller.process_disconnected();
-                }
-                update_displays(&display, &controllers, page_num);
-            }
-            Message::ButtonPress(ButtonId::Physical(id_in_page)) => {
-                if status.display_on {
-                    let id = page_to_controller_id(page_num, id_in_page);
-                    button_press(&mut controllers, id, &mqtt);
-                    display
-                        .send(DisplayCommand::ButtonPressed(id_in_page))
-                        .unwrap();
-                }
-                requested_display_status.turn_night_timer_on();
-                do_blank(
-                    &display,
-                    &mut timer,
-                    &requested_display_status,
-                    &mut status,
-                    true,
-                );
-            }
-            Message::ButtonPress(ButtonId::Controller(id)) => {
-                button_press(&mut controllers, id, &mqtt);
-                let (msg_page_num, id_in_page) = controller_to_page_id(id);
-                if msg_page_num == page_num {
-                    display
-                        .send(DisplayCommand::ButtonPressed(id_in_page))
-                        .unwrap();
-                }
-                requested_display_status.turn_night_timer_on();
-                do_blank(
-                    &display,
-                    &mut timer,
-                    &requested_display_status,
-                    &mut status,
-                    true,
-                );
-            }
-            Message::ButtonPress(ButtonId::PageUp) => {
-                info!("got page up");
-                page_num = page_num.saturating_add(1);
-                if page_num > last_page {
-                    page_num = last_page
-                };
-                display.send(DisplayCommand::ShowPage(page_num)).unwrap();
-                update_displays(&display, &controllers, page_num);
-                requested_display_status.turn_night_timer_on();
-                do_blank(
-                    &display,
-                    &mut timer,
-                    &requested_display_status,
-                    &mut status,
-                    true,
-                );
-            }
-            Message::ButtonPress(ButtonId::PageDown) => {
-                info!("got page down");
-                page_num = page_num.saturating_sub(1);
-                display.send(DisplayCommand::ShowPage(page_num)).unwrap();
-                update_displays(&display, &controllers, page_num);
-                requested_display_status.turn_night_timer_on();
-                do_blank(
-                    &display,
-                    &mut timer,
-                    &requested_display_status,
-                    &mut status,
-                    true,
-                );
-            }
-            Message::ButtonPress(ButtonId::NotAButton) => {
-                info!("Got not a button press");
-                requested_display_status.turn_night_timer_on();
-                do_blank(
-                    &display,
-                    &mut timer,
-                    &requested_display_status,
-                    &mut status,
-                    true,
-                );
-            }
-            Message::ButtonRelease(ButtonId::Physical(id_in_page)) => {
-                info!("Got button release");
-                display
-                    .send(DisplayCommand::ButtonReleased(id_in_page))
-                    .unwrap();
-                requested_display_status.turn_night_timer_on();
-                do_blank(
-                    &display,
-                    &mut timer,
-                    &requested_display_status,
-                    &mut status,
-                    true,
-                );
-            }
-            Message::ButtonRelease(ButtonId::Controller(id)) => {
-                info!("Got button release");
-                let (msg_page_num, id_in_page) = controller_to_page_id(id);
-                if msg_page_num == page_num {
-                    display
-                        .send(DisplayCommand::ButtonReleased(id_in_page))
-                        .unwrap();
-                }
-                requested_display_status.turn_night_timer_on();
-                do_blank(
-                    &display,
-                    &mut timer,
-                    &requested_display_status,
-                    &mut status,
-                    true,
-                );
-            }
-            Message::ButtonRelease(_) => {
-                info!("Got button release");
-                requested_display_status.turn_night_timer_on();
-                do_blank(
-                    &display,
-                    &mut timer,
-                    &requested_display_status,
-                    &mut status,
-                    true,
-                );
-            }
-            Message::BlankDisplays => {
-                info!("Got blank display timer");
-                requested_display_status.turn_night_timer_off();
-                do_blank(
-                    &display,
-                    &mut timer,
-                    &requested_display_status,
-                    &mut status,
-                    true,
-                );
-            }
-        }
+        let effects = state.handle(received);
+        outputs.do_effects(effects);
     }
 
     Ok(())
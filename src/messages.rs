@@ -1,16 +1,23 @@
 use crate::button;
 use crate::messages;
 use crate::mqtt;
+use crate::supervisor;
 
 pub enum Message {
+    MqttConnecting,
     MqttConnect,
     MqttDisconnect,
-    MqttReceived(String, String, mqtt::Label),
+    MqttAuthFailed,
+    MqttError(String),
+    MqttReceived(String, mqtt::Payload, mqtt::Label),
     #[allow(dead_code)]
     ButtonPress(button::ButtonId),
     #[allow(dead_code)]
     ButtonRelease(button::ButtonId),
     BlankDisplays,
+    Telemetry,
+    Clock,
+    WorkerFailed(supervisor::Failure),
 }
 
 pub type Sender = std::sync::mpsc::Sender<messages::Message>;
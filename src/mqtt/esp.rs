@@ -0,0 +1,247 @@
+use std::ffi::c_void;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_svc::{
+    mqtt::client::{Connection, Details, Event, Message, MessageImpl, QoS},
+    utils::mqtt::client::ConnState,
+};
+
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::mqtt::client::{EspMqttClient, LwtConfiguration, MqttClientConfiguration};
+use esp_idf_svc::tls::X509;
+use esp_idf_sys::esp;
+use esp_idf_sys::EspError;
+
+use log::*;
+
+use crate::hardware::get_unique_id;
+use crate::hardware::Storage;
+use crate::supervisor::Permanent;
+
+use super::reassemble::Chunk;
+use super::reassemble::Reassembler;
+use super::Events;
+use super::Transport;
+use super::Will;
+
+fn event_to_string(event: &Event<MessageImpl>) -> String {
+    match event {
+        Event::BeforeConnect => "BeforeConnect".to_string(),
+        Event::Connected(connected) => format!("Connected(connected: {connected})"),
+        Event::Disconnected => "Disconnected".to_string(),
+        Event::Subscribed(message_id) => format!("Subscribed({message_id})"),
+        Event::Unsubscribed(message_id) => format!("Unsubscribed({message_id})"),
+        Event::Published(message_id) => format!("Published({message_id})"),
+        Event::Received(message) => format!("Received({})", message.id()),
+        Event::Deleted(message_id) => format!("Deleted({message_id})"),
+    }
+}
+
+/// How long to wait before trying to connect again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+const CA_CERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/mqtt_ca_cert.pem"));
+const CLIENT_CERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/mqtt_client_cert.pem"));
+const CLIENT_KEY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/mqtt_client_key.pem"));
+
+/// PEM certificates for `mqtts://` connections.
+///
+/// ESP-IDF keeps pointers to these rather than copies, so they live for the
+/// rest of the program.
+pub struct Certificates {
+    ca: Option<&'static [u8]>,
+    client_cert: Option<&'static [u8]>,
+    client_key: Option<&'static [u8]>,
+}
+
+impl Certificates {
+    /// Use the certificates stored in NVS, falling back to the ones embedded
+    /// at build time.
+    pub fn load(storage: &Storage) -> Result<Self> {
+        Ok(Certificates {
+            ca: load_pem(storage, "mqtt_ca", CA_CERT)?,
+            client_cert: load_pem(storage, "mqtt_cert", CLIENT_CERT)?,
+            client_key: load_pem(storage, "mqtt_key", CLIENT_KEY)?,
+        })
+    }
+}
+
+fn load_pem(
+    storage: &Storage,
+    key: &str,
+    embedded: &'static [u8],
+) -> Result<Option<&'static [u8]>> {
+    let mut data = match storage.load(key)? {
+        Some(data) => data,
+        None if embedded.is_empty() => return Ok(None),
+        None => embedded.to_vec(),
+    };
+
+    // ESP-IDF wants them NUL terminated.
+    if data.last() != Some(&0) {
+        data.push(0);
+    }
+    Ok(Some(Box::leak(data.into_boxed_slice())))
+}
+
+fn get_chunk(msg: &MessageImpl) -> Chunk {
+    match msg.details() {
+        Details::Complete => Chunk::Complete,
+        Details::InitialChunk(chunk) => Chunk::Initial {
+            total: chunk.total_data_size,
+        },
+        Details::SubsequentChunk(chunk) => Chunk::Subsequent {
+            offset: chunk.current_data_offset,
+        },
+    }
+}
+
+/// Raw ESP-IDF handler for MQTT errors.
+///
+/// The connection only gets an `EspError` for a refused connection, which
+/// doesn't say why, so look at the return code here.
+unsafe extern "C" fn on_mqtt_error(
+    arg: *mut c_void,
+    _base: esp_idf_sys::esp_event_base_t,
+    _id: i32,
+    data: *mut c_void,
+) {
+    let events = &*(arg as *const Events);
+    let event = &*(data as *const esp_idf_sys::esp_mqtt_event_t);
+    let Some(error) = event.error_handle.as_ref() else {
+        return;
+    };
+
+    if error.error_type == esp_idf_sys::esp_mqtt_error_type_t_MQTT_ERROR_TYPE_CONNECTION_REFUSED
+        && matches!(
+            error.connect_return_code,
+            esp_idf_sys::esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_BAD_USERNAME
+                | esp_idf_sys::esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_NOT_AUTHORIZED
+        )
+    {
+        events.auth_failed();
+    }
+}
+
+pub struct EspTransport {
+    client: EspMqttClient<ConnState<MessageImpl, EspError>>,
+    /// Given to the error handler registered with the client. Fields are
+    /// dropped in order, so this goes after the client that uses it.
+    _error_events: Box<Events>,
+}
+
+impl EspTransport {
+    pub fn new(
+        url: &str,
+        certificates: &Certificates,
+        will: Option<Will>,
+        max_message_size: usize,
+        events: Events,
+    ) -> Result<Self> {
+        // Trying again won't help with these.
+        if url.starts_with("mqtts://") && certificates.ca.is_none() {
+            let message = "mqtts:// needs a CA certificate".to_string();
+            return Err(Permanent(message).into());
+        }
+        if certificates.client_cert.is_some() != certificates.client_key.is_some() {
+            let message = "Client certificate and key must be given together".to_string();
+            return Err(Permanent(message).into());
+        }
+
+        let client_id = format!("robotica-remote-rust_{}", get_unique_id());
+        let lwt = will.as_ref().map(|will| LwtConfiguration {
+            topic: &will.topic,
+            payload: will.payload.as_bytes(),
+            qos: QoS::AtLeastOnce,
+            retain: will.retain,
+        });
+        let conf = MqttClientConfiguration {
+            client_id: Some(&client_id),
+            keep_alive_interval: Some(std::time::Duration::new(60, 0)),
+            reconnect_timeout: Some(RECONNECT_DELAY),
+            lwt,
+            server_certificate: certificates.ca.map(X509::pem_until_nul),
+            client_certificate: certificates.client_cert.map(X509::pem_until_nul),
+            private_key: certificates.client_key.map(X509::pem_until_nul),
+            skip_cert_common_name_check: false,
+            ..Default::default()
+        };
+
+        let (client, mut connection) = EspMqttClient::new_with_conn(url, &conf)?;
+
+        let error_events = Box::new(events.clone());
+        esp!(unsafe {
+            esp_idf_sys::esp_mqtt_client_register_event(
+                client.handle(),
+                esp_idf_sys::esp_mqtt_event_id_t_MQTT_EVENT_ERROR,
+                Some(on_mqtt_error),
+                &*error_events as *const Events as *mut c_void,
+            )
+        })?;
+
+        thread::spawn(move || {
+            let mut reassembler = Reassembler::new(max_message_size);
+
+            while let Some(msg) = connection.next() {
+                let event = match msg.as_ref() {
+                    Ok(event) => event,
+                    Err(err) => {
+                        // This is how a failed TLS handshake is reported.
+                        error!("MQTT error: {err}");
+                        events.error(format!("MQTT: {err}"));
+                        continue;
+                    }
+                };
+                match event {
+                    Event::Received(msg) => {
+                        let topic = msg.topic().unwrap_or_default();
+                        let chunk = get_chunk(msg);
+                        if let Some((topic, data)) =
+                            reassembler.add(msg.id(), &topic, chunk, msg.data())
+                        {
+                            events.received(topic, data);
+                        }
+                    }
+                    Event::BeforeConnect => {
+                        events.connecting();
+                    }
+                    Event::Connected(_) => {
+                        events.connected();
+                    }
+                    Event::Disconnected => {
+                        events.disconnected();
+                    }
+                    Event::Subscribed(_x) => {
+                        // Do nothing
+                    }
+                    event => info!("Got unknown MQTT event {:?}", event_to_string(event)),
+                }
+            }
+        });
+
+        Ok(EspTransport {
+            client,
+            _error_events: error_events,
+        })
+    }
+}
+
+impl Transport for EspTransport {
+    fn subscribe(&mut self, topic: &str) -> Result<()> {
+        self.client.subscribe(topic, QoS::AtMostOnce)?;
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, topic: &str) -> Result<()> {
+        self.client.unsubscribe(topic)?;
+        Ok(())
+    }
+
+    fn publish(&mut self, topic: &str, retain: bool, data: &[u8]) -> Result<()> {
+        self.client.publish(topic, QoS::AtMostOnce, retain, data)?;
+        Ok(())
+    }
+}
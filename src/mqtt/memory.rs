@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use log::*;
+
+use super::topic;
+use super::Events;
+use super::Transport;
+use super::Will;
+
+struct Client {
+    events: Events,
+    filters: Vec<String>,
+    will: Option<Will>,
+}
+
+struct Observer {
+    filter: String,
+    tx: mpsc::Sender<(String, Vec<u8>)>,
+}
+
+struct Inner {
+    connected: bool,
+    clients: Vec<Option<Client>>,
+    observers: Vec<Observer>,
+    retained: HashMap<String, Vec<u8>>,
+}
+
+impl Inner {
+    fn publish(&mut self, topic: &str, retain: bool, data: &[u8]) {
+        if retain {
+            if data.is_empty() {
+                self.retained.remove(topic);
+            } else {
+                self.retained.insert(topic.to_string(), data.to_vec());
+            }
+        }
+
+        if self.connected {
+            for client in self.clients.iter().flatten() {
+                if client.filters.iter().any(|f| topic::matches(f, topic)) {
+                    client.events.received(topic.to_string(), data.to_vec());
+                }
+            }
+        }
+
+        // Observers are on the far side of the network, so they still see
+        // wills published while it is down.
+        self.observers.retain(|observer| {
+            !topic::matches(&observer.filter, topic)
+                || observer.tx.send((topic.to_string(), data.to_vec())).is_ok()
+        });
+    }
+}
+
+/// An in process broker, for running without a network.
+///
+/// Clients connect with [`Broker::connect`] and get a [`Transport`]. Anyone
+/// holding the broker can publish messages, watch what gets published and
+/// break or restore the connection of every client.
+#[derive(Clone)]
+pub struct Broker(Arc<Mutex<Inner>>);
+
+impl Broker {
+    pub fn new() -> Self {
+        Broker(Arc::new(Mutex::new(Inner {
+            connected: true,
+            clients: Vec::new(),
+            observers: Vec::new(),
+            retained: HashMap::new(),
+        })))
+    }
+
+    /// Connect a client. Its will is published if the connection is broken
+    /// with [`Broker::set_connected`], or if the transport is dropped, as
+    /// happens when the client fails, since it never disconnects cleanly.
+    pub fn connect(&self, events: Events, will: Option<Will>) -> MemoryTransport {
+        let mut inner = self.0.lock().unwrap();
+
+        events.connecting();
+        if inner.connected {
+            events.connected();
+        }
+
+        let id = inner.clients.len();
+        inner.clients.push(Some(Client {
+            events,
+            filters: Vec::new(),
+            will,
+        }));
+
+        MemoryTransport {
+            broker: self.clone(),
+            id,
+        }
+    }
+
+    pub fn publish(&self, topic: &str, retain: bool, data: &[u8]) {
+        let mut inner = self.0.lock().unwrap();
+        inner.publish(topic, retain, data);
+    }
+
+    /// Get every message published to topics matching `filter` from now on.
+    pub fn observe(&self, filter: &str) -> mpsc::Receiver<(String, Vec<u8>)> {
+        let (tx, rx) = mpsc::channel();
+        let mut inner = self.0.lock().unwrap();
+        inner.observers.push(Observer {
+            filter: filter.to_string(),
+            tx,
+        });
+        rx
+    }
+
+    pub fn get_retained(&self, topic: &str) -> Option<Vec<u8>> {
+        let inner = self.0.lock().unwrap();
+        inner.retained.get(topic).cloned()
+    }
+
+    /// Simulate the network going down or coming back.
+    ///
+    /// Sessions are not persistent, so clients need to subscribe again after
+    /// reconnecting.
+    pub fn set_connected(&self, connected: bool) {
+        self.change_connected(connected, false);
+    }
+
+    /// Simulate the network going down because the broker refused our
+    /// credentials.
+    pub fn refuse_auth(&self) {
+        self.change_connected(false, true);
+    }
+
+    fn change_connected(&self, connected: bool, refused: bool) {
+        let mut inner = self.0.lock().unwrap();
+        if inner.connected == connected {
+            return;
+        }
+        inner.connected = connected;
+
+        let mut wills = Vec::new();
+        for client in inner.clients.iter_mut().flatten() {
+            if connected {
+                client.events.connecting();
+                client.events.connected();
+            } else {
+                client.filters.clear();
+                if refused {
+                    client.events.auth_failed();
+                }
+                client.events.disconnected();
+                wills.extend(client.will.clone());
+            }
+        }
+
+        for will in wills {
+            inner.publish(&will.topic, will.retain, will.payload.as_bytes());
+        }
+    }
+}
+
+pub struct MemoryTransport {
+    broker: Broker,
+    id: usize,
+}
+
+impl Transport for MemoryTransport {
+    fn subscribe(&mut self, filter: &str) -> Result<()> {
+        let mut inner = self.broker.0.lock().unwrap();
+        if !inner.connected {
+            warn!(
+                "Memory broker dropped subscribe to {} while disconnected",
+                filter
+            );
+            return Ok(());
+        }
+
+        let inner = &mut *inner;
+        let client = inner.clients[self.id].as_mut().unwrap();
+        if !client.filters.iter().any(|f| f == filter) {
+            client.filters.push(filter.to_string());
+        }
+
+        for (topic, data) in inner.retained.iter() {
+            if topic::matches(filter, topic) {
+                client.events.received(topic.clone(), data.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, filter: &str) -> Result<()> {
+        let mut inner = self.broker.0.lock().unwrap();
+        let client = inner.clients[self.id].as_mut().unwrap();
+        client.filters.retain(|f| f != filter);
+        Ok(())
+    }
+
+    fn publish(&mut self, topic: &str, retain: bool, data: &[u8]) -> Result<()> {
+        if !self.broker.0.lock().unwrap().connected {
+            warn!(
+                "Memory broker dropped publish to {} while disconnected",
+                topic
+            );
+            return Ok(());
+        }
+        debug!(
+            "Memory broker got {} {}",
+            topic,
+            String::from_utf8_lossy(data)
+        );
+        self.broker.publish(topic, retain, data);
+        Ok(())
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        let mut inner = self.broker.0.lock().unwrap();
+        let Some(client) = inner.clients[self.id].take() else {
+            return;
+        };
+        // Already published if the connection is down.
+        if let (true, Some(will)) = (inner.connected, client.will) {
+            inner.publish(&will.topic, will.retain, will.payload.as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::button::ButtonId;
+    use crate::config::Config;
+    use crate::messages::Message;
+    use crate::mqtt::Decoding;
+    use crate::mqtt::Label;
+    use crate::mqtt::Mqtt;
+    use crate::mqtt::Payload;
+    use crate::mqtt::Subscriptions;
+    use crate::state::Effect;
+    use crate::state::RemoteState;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Start a client, and wait for it to connect and subscribe.
+    fn connect(
+        broker: &Broker,
+        subscriptions: Subscriptions,
+        will: Option<Will>,
+    ) -> (Mqtt, mpsc::Receiver<Message>) {
+        let (tx, rx) = mpsc::channel();
+        let broker = broker.clone();
+        let mqtt = Mqtt::connect(
+            move |events| Ok(broker.connect(events, will.clone())),
+            tx,
+            subscriptions,
+        )
+        .unwrap();
+
+        loop {
+            match rx.recv_timeout(TIMEOUT).unwrap() {
+                Message::MqttConnecting => {}
+                Message::MqttConnect => break,
+                _ => panic!("Unexpected message while connecting"),
+            }
+        }
+        (mqtt, rx)
+    }
+
+    fn next_received(rx: &mpsc::Receiver<Message>) -> (String, Payload, Label) {
+        match rx.recv_timeout(TIMEOUT).unwrap() {
+            Message::MqttReceived(topic, payload, label) => (topic, payload, label),
+            _ => panic!("Expected a received message"),
+        }
+    }
+
+    fn received(topic: &str, text: &str, label: Label) -> (String, Payload, Label) {
+        (topic.to_string(), Payload::Text(text.to_string()), label)
+    }
+
+    fn publish_effects(mqtt: &Mqtt, effects: Vec<Effect>) {
+        for effect in effects {
+            if let Effect::Publish {
+                topic,
+                retain,
+                payload,
+            } = effect
+            {
+                mqtt.publish(&topic, retain, &payload);
+            }
+        }
+    }
+
+    #[test]
+    fn test_retained_delivered_on_subscribe() {
+        let broker = Broker::new();
+        let (publisher, _publisher_rx) = connect(&broker, Subscriptions::new(), None);
+        let published = broker.observe("#");
+        publisher.publish("state/Brian/Fan/power", true, "ON");
+        publisher.publish("state/Brian/Fan/other", false, "ignored");
+        assert_eq!(published.recv_timeout(TIMEOUT).unwrap().1, b"ON");
+        assert_eq!(published.recv_timeout(TIMEOUT).unwrap().1, b"ignored");
+
+        let mut subscriptions = Subscriptions::new();
+        subscriptions.add("state/+/Fan/#", Label::Button(2, 1), Decoding::Text);
+        let (_subscriber, rx) = connect(&broker, subscriptions, None);
+
+        let expected = received("state/Brian/Fan/power", "ON", Label::Button(2, 1));
+        assert_eq!(next_received(&rx), expected);
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn test_set_subscriptions_routes_to_new_labels() {
+        let broker = Broker::new();
+        broker.publish("a/b", true, b"1");
+        broker.publish("c/d", true, b"2");
+
+        let mut subscriptions = Subscriptions::new();
+        subscriptions.add("a/b", Label::Config, Decoding::Text);
+        let (client, rx) = connect(&broker, subscriptions, None);
+        assert_eq!(next_received(&rx), received("a/b", "1", Label::Config));
+
+        let mut subscriptions = Subscriptions::new();
+        subscriptions.add("a/+", Label::Command, Decoding::Text);
+        subscriptions.add("c/#", Label::Button(1, 0), Decoding::Text);
+        subscriptions.add("c/d", Label::BlankCondition(0), Decoding::Json);
+        client.set_subscriptions(subscriptions);
+
+        // The retained messages again, once for each filter they match.
+        let got: Vec<_> = (0..5).map(|_| next_received(&rx)).collect();
+        let count =
+            |expected: &(String, Payload, Label)| got.iter().filter(|g| *g == expected).count();
+        assert_eq!(count(&received("a/b", "1", Label::Command)), 1);
+        assert_eq!(count(&received("c/d", "2", Label::Button(1, 0))), 2);
+        let json = (
+            "c/d".to_string(),
+            Payload::Json(2.into()),
+            Label::BlankCondition(0),
+        );
+        assert_eq!(count(&json), 2);
+
+        // Nothing for the old label.
+        broker.publish("a/b", false, b"3");
+        assert_eq!(next_received(&rx), received("a/b", "3", Label::Command));
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn test_button_press_publishes_command() {
+        let broker = Broker::new();
+        let config = Config::default_for(2);
+        // The fan, first on the second page.
+        let fan = config.pages[1].slots[0]
+            .as_ref()
+            .unwrap()
+            .create_controller();
+        let expected = fan.get_press_commands();
+        assert!(!expected.is_empty());
+
+        let mut subscriptions = Subscriptions::new();
+        subscriptions.add("command/#", Label::Command, Decoding::Text);
+        let (_listener, rx) = connect(&broker, subscriptions, None);
+
+        let mut state = RemoteState::new(config, 2, "test");
+        let subscriptions = state.get_subscriptions();
+        let will = Some(state.get_will());
+        let (remote, _remote_rx) = connect(&broker, subscriptions, will);
+        publish_effects(&remote, state.start());
+        publish_effects(&remote, state.handle(Message::MqttConnect));
+        let press = Message::ButtonPress(ButtonId::Controller(2));
+        publish_effects(&remote, state.handle(press));
+
+        for command in expected {
+            let expected = received(&command.topic, &command.payload, Label::Command);
+            assert_eq!(next_received(&rx), expected);
+        }
+    }
+
+    #[test]
+    fn test_will_published_on_drop() {
+        let broker = Broker::new();
+        let mut subscriptions = Subscriptions::new();
+        subscriptions.add("state/+/availability", Label::Config, Decoding::Text);
+        let (_listener, rx) = connect(&broker, subscriptions, None);
+
+        let (tx, _events_rx) = mpsc::channel();
+        let will = Will {
+            topic: "state/test/availability".to_string(),
+            payload: "offline".to_string(),
+            retain: true,
+        };
+        let transport = broker.connect(Events(tx), Some(will));
+        drop(transport);
+
+        let expected = received("state/test/availability", "offline", Label::Config);
+        assert_eq!(next_received(&rx), expected);
+        let retained = broker.get_retained("state/test/availability");
+        assert_eq!(retained.as_deref(), Some(b"offline".as_slice()));
+    }
+
+    #[test]
+    fn test_bytes_delivered_unchanged() {
+        let broker = Broker::new();
+        let data = [0xff, 0x00, 0xfe];
+        broker.publish("raw/data", true, &data);
+        assert_eq!(broker.get_retained("raw/data"), Some(data.to_vec()));
+
+        let mut subscriptions = Subscriptions::new();
+        subscriptions.add("raw/#", Label::Config, Decoding::Raw);
+        let (_client, rx) = connect(&broker, subscriptions, None);
+        let expected = (
+            "raw/data".to_string(),
+            Payload::Raw(data.to_vec()),
+            Label::Config,
+        );
+        assert_eq!(next_received(&rx), expected);
+    }
+
+    #[test]
+    fn test_decode_failure_counted() {
+        let broker = Broker::new();
+        let mut subscriptions = Subscriptions::new();
+        subscriptions.add("state/+/power", Label::BlankCondition(0), Decoding::Json);
+        let (client, rx) = connect(&broker, subscriptions, None);
+
+        broker.publish("state/Brian/power", false, b"{not json");
+        broker.publish("state/Brian/power", false, b"true");
+
+        // Still subscribed after the bad payload.
+        let expected = (
+            "state/Brian/power".to_string(),
+            Payload::Json(true.into()),
+            Label::BlankCondition(0),
+        );
+        assert_eq!(next_received(&rx), expected);
+        assert_eq!(client.get_decode_failures(), 1);
+    }
+}
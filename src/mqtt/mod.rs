@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use log::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::messages;
+use crate::supervisor;
+
+#[cfg(target_os = "espidf")]
+pub mod esp;
+
+pub mod memory;
+#[cfg(any(target_os = "espidf", test))]
+pub mod reassemble;
+pub mod topic;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Label {
+    Button(usize, u32),
+    BlankCondition(usize),
+    Config,
+    Command,
+}
+
+/// What the person holding the remote gets told about the connection.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    /// Lost the connection, and waiting to try again.
+    Reconnecting,
+    AuthFailed,
+}
+
+/// How to decode the payloads delivered to a subscription.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decoding {
+    #[default]
+    Text,
+    Json,
+    Raw,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Payload {
+    Text(String),
+    Json(serde_json::Value),
+    Raw(Vec<u8>),
+}
+
+impl Decoding {
+    fn decode(self, data: &[u8]) -> Result<Payload> {
+        let payload = match self {
+            Decoding::Text => Payload::Text(std::str::from_utf8(data)?.to_string()),
+            Decoding::Json => Payload::Json(serde_json::from_slice(data)?),
+            Decoding::Raw => Payload::Raw(data.to_vec()),
+        };
+        Ok(payload)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Subscription {
+    label: Label,
+    decoding: Decoding,
+}
+
+/// Labels to deliver messages to, by topic filter.
+///
+/// Filters may contain `+` and `#` wildcards. The handler is given the
+/// concrete topic the message arrived on.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Subscriptions {
+    filters: HashMap<String, Vec<Subscription>>,
+    tree: topic::FilterTree<String>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Subscriptions {
+            filters: HashMap::new(),
+            tree: topic::FilterTree::new(),
+        }
+    }
+
+    pub fn add(&mut self, filter: &str, label: Label, decoding: Decoding) {
+        let subscriptions = self.filters.entry(filter.to_string()).or_insert_with(|| {
+            self.tree.insert(filter, filter.to_string());
+            Vec::new()
+        });
+        subscriptions.push(Subscription { label, decoding });
+    }
+
+    fn get_matches(&self, topic: &str) -> impl Iterator<Item = &Subscription> {
+        self.tree
+            .get_matches(topic)
+            .into_iter()
+            .flat_map(|filter| self.filters[filter].iter())
+    }
+}
+
+/// Message the broker publishes for us if we go away without disconnecting.
+#[derive(Clone, Debug)]
+pub struct Will {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+/// A connection to a broker.
+///
+/// Implementations report connection state and incoming messages through
+/// the [`Events`] they were created with.
+pub trait Transport {
+    fn subscribe(&mut self, topic: &str) -> Result<()>;
+    fn unsubscribe(&mut self, topic: &str) -> Result<()>;
+    fn publish(&mut self, topic: &str, retain: bool, data: &[u8]) -> Result<()>;
+}
+
+enum MqttCommand {
+    MqttConnecting,
+    MqttConnect,
+    MqttAuthFailed,
+    MqttDisconnect,
+    MqttError(String),
+    MqttReceived(String, Vec<u8>),
+    Publish(String, bool, String),
+    SetSubscriptions(Subscriptions),
+}
+
+#[derive(Clone)]
+pub struct Events(mpsc::Sender<MqttCommand>);
+
+impl Events {
+    pub fn connecting(&self) {
+        self.send(MqttCommand::MqttConnecting);
+    }
+
+    /// The broker refused our credentials.
+    pub fn auth_failed(&self) {
+        self.send(MqttCommand::MqttAuthFailed);
+    }
+
+    pub fn connected(&self) {
+        self.send(MqttCommand::MqttConnect);
+    }
+
+    pub fn disconnected(&self) {
+        self.send(MqttCommand::MqttDisconnect);
+    }
+
+    pub fn error(&self, message: String) {
+        self.send(MqttCommand::MqttError(message));
+    }
+
+    pub fn received(&self, topic: String, data: Vec<u8>) {
+        self.send(MqttCommand::MqttReceived(topic, data));
+    }
+
+    // Called from the transport, which has nowhere to return an error to.
+    fn send(&self, command: MqttCommand) {
+        if self.0.send(command).is_err() {
+            error!("MQTT thread has gone, dropping event");
+        }
+    }
+}
+
+pub struct Mqtt {
+    tx: mpsc::Sender<MqttCommand>,
+    /// Payloads that could not be decoded, since boot.
+    decode_failures: Arc<AtomicU32>,
+}
+
+/// Connect, and process commands until the client fails.
+fn run<T, F>(
+    connect: &mut F,
+    events: Events,
+    rx: &mpsc::Receiver<MqttCommand>,
+    tx_to_client: &messages::Sender,
+    subscriptions: &mut Subscriptions,
+    decode_failures: &AtomicU32,
+) -> Result<()>
+where
+    T: Transport,
+    F: FnMut(Events) -> Result<T>,
+{
+    let mut client = match connect(events) {
+        Ok(client) => client,
+        Err(err) => {
+            tx_to_client.send(messages::Message::MqttError(format!("MQTT: {err}")))?;
+            return Err(err.context("MQTT connect failed"));
+        }
+    };
+    let mut connected = false;
+
+    for received in rx.iter() {
+        match received {
+            MqttCommand::MqttConnecting => {
+                tx_to_client.send(messages::Message::MqttConnecting)?;
+            }
+
+            MqttCommand::MqttAuthFailed => {
+                tx_to_client.send(messages::Message::MqttAuthFailed)?;
+            }
+
+            MqttCommand::MqttConnect => {
+                connected = true;
+                for filter in subscriptions.filters.keys() {
+                    client.subscribe(filter)?;
+                }
+                tx_to_client.send(messages::Message::MqttConnect)?;
+            }
+
+            MqttCommand::MqttDisconnect => {
+                connected = false;
+                tx_to_client.send(messages::Message::MqttDisconnect)?;
+            }
+
+            MqttCommand::MqttError(message) => {
+                tx_to_client.send(messages::Message::MqttError(message))?;
+            }
+
+            MqttCommand::MqttReceived(topic, data) => {
+                for s in subscriptions.get_matches(&topic) {
+                    let payload = match s.decoding.decode(&data) {
+                        Ok(payload) => payload,
+                        Err(err) => {
+                            let failures = decode_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                            error!(
+                                "Could not decode {} as {:?}, {} failures so far: {}",
+                                topic, s.decoding, failures, err
+                            );
+                            continue;
+                        }
+                    };
+                    tx_to_client.send(messages::Message::MqttReceived(
+                        topic.clone(),
+                        payload,
+                        s.label.clone(),
+                    ))?;
+                }
+            }
+
+            MqttCommand::Publish(topic, retain, data) => {
+                debug!("Publishing {} {}", topic, data);
+                client.publish(&topic, retain, data.as_bytes())?;
+            }
+
+            MqttCommand::SetSubscriptions(new_subscriptions) => {
+                if connected {
+                    for filter in subscriptions.filters.keys() {
+                        if !new_subscriptions.filters.contains_key(filter) {
+                            client.unsubscribe(filter)?;
+                        }
+                    }
+                    // Subscribe again even if we already were, so
+                    // we get the retained values for the new labels.
+                    for filter in new_subscriptions.filters.keys() {
+                        client.subscribe(filter)?;
+                    }
+                }
+                *subscriptions = new_subscriptions;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl Mqtt {
+    /// Start the MQTT thread, calling `connect` again for a new client
+    /// each time the old one fails.
+    pub fn connect<T, F>(
+        mut connect: F,
+        tx_to_client: messages::Sender,
+        subscriptions: Subscriptions,
+    ) -> Result<Self>
+    where
+        T: Transport,
+        F: FnMut(Events) -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+
+        let events = Events(tx.clone());
+        let mut subscriptions = subscriptions;
+        let decode_failures = Arc::new(AtomicU32::new(0));
+        let worker_decode_failures = decode_failures.clone();
+
+        supervisor::spawn("mqtt", None, tx_to_client.clone(), move || {
+            let result = run(
+                &mut connect,
+                events.clone(),
+                &rx,
+                &tx_to_client,
+                &mut subscriptions,
+                &worker_decode_failures,
+            );
+            match &result {
+                // Leave the error on the display, there won't be a new
+                // client.
+                Err(err) if supervisor::is_permanent(err) => {}
+                Err(_) => {
+                    // The old client is gone, the new one will say when it
+                    // has connected.
+                    tx_to_client.send(messages::Message::MqttDisconnect)?;
+                }
+                Ok(()) => {}
+            }
+            result
+        })?;
+
+        Ok(Mqtt {
+            tx,
+            decode_failures,
+        })
+    }
+
+    pub fn publish(&self, topic: &str, retain: bool, data: &str) {
+        let command = MqttCommand::Publish(topic.to_string(), retain, data.to_string());
+        if self.tx.send(command).is_err() {
+            error!("MQTT thread has gone, could not publish to {topic}");
+        }
+    }
+
+    pub fn get_decode_failures(&self) -> u32 {
+        self.decode_failures.load(Ordering::Relaxed)
+    }
+
+    pub fn set_subscriptions(&self, subscriptions: Subscriptions) {
+        let command = MqttCommand::SetSubscriptions(subscriptions);
+        if self.tx.send(command).is_err() {
+            error!("MQTT thread has gone, could not subscribe");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let text = |text: &str| Payload::Text(text.to_string());
+        let cases: &[(Decoding, &[u8], Option<Payload>)] = &[
+            (Decoding::Text, b"ON", Some(text("ON"))),
+            (Decoding::Text, "Café".as_bytes(), Some(text("Café"))),
+            (Decoding::Text, b"", Some(text(""))),
+            (Decoding::Text, b"\xff\xfe", None),
+            (Decoding::Text, b"caf\xc3", None),
+            (
+                Decoding::Json,
+                br#"{"on": true}"#,
+                Some(Payload::Json(serde_json::json!({ "on": true }))),
+            ),
+            (Decoding::Json, b"12", Some(Payload::Json(12.into()))),
+            (Decoding::Json, b"ON", None),
+            (Decoding::Json, br#"{"on": "#, None),
+            (Decoding::Json, b"", None),
+            (Decoding::Json, b"\xff", None),
+            (
+                Decoding::Raw,
+                b"\xff\x00",
+                Some(Payload::Raw(vec![0xff, 0])),
+            ),
+            (Decoding::Raw, b"", Some(Payload::Raw(vec![]))),
+        ];
+        for (decoding, data, expected) in cases {
+            let decoded = decoding.decode(data).ok();
+            assert_eq!(decoded, *expected, "{data:?} as {decoding:?}");
+        }
+    }
+}
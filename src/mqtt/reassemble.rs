@@ -0,0 +1,304 @@
+//! Putting MQTT messages that arrive in chunks back together.
+//!
+//! ESP-IDF splits messages too big for its buffer into chunks, and only the
+//! first chunk says which topic it is for. Chunks of different messages are
+//! never interleaved.
+
+use log::*;
+
+/// Which part of a message some data is.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Chunk {
+    /// All of it.
+    Complete,
+    /// The start of a message that is `total` bytes long.
+    Initial { total: usize },
+    /// More of a message, starting `offset` bytes in.
+    Subsequent { offset: usize },
+}
+
+struct Partial {
+    id: u32,
+    topic: String,
+    data: Vec<u8>,
+    total: usize,
+}
+
+pub struct Reassembler {
+    max_size: usize,
+    partial: Option<Partial>,
+}
+
+impl Reassembler {
+    /// Messages over `max_size` bytes are dropped, without buffering them.
+    pub fn new(max_size: usize) -> Self {
+        Reassembler {
+            max_size,
+            partial: None,
+        }
+    }
+
+    /// Add a chunk of message `id`, and get the message back once it is
+    /// complete. `topic` is ignored for subsequent chunks, which don't have
+    /// one.
+    pub fn add(
+        &mut self,
+        id: u32,
+        topic: &str,
+        chunk: Chunk,
+        data: &[u8],
+    ) -> Option<(String, Vec<u8>)> {
+        match chunk {
+            Chunk::Complete => {
+                self.abandon(topic);
+                if data.len() > self.max_size {
+                    self.report_oversize(topic, data.len());
+                    return None;
+                }
+                Some((topic.to_string(), data.to_vec()))
+            }
+            Chunk::Initial { total } => {
+                self.abandon(topic);
+                if total > self.max_size {
+                    self.report_oversize(topic, total);
+                    return None;
+                }
+                self.partial = Some(Partial {
+                    id,
+                    topic: topic.to_string(),
+                    data: Vec::with_capacity(total),
+                    total,
+                });
+                self.extend(id, 0, data)
+            }
+            Chunk::Subsequent { offset } => self.extend(id, offset, data),
+        }
+    }
+
+    fn extend(&mut self, id: u32, offset: usize, data: &[u8]) -> Option<(String, Vec<u8>)> {
+        // Missing if the start was dropped, in which case so is this.
+        let mut partial = self.partial.take()?;
+
+        if id != partial.id {
+            warn!(
+                "Dropping incomplete MQTT message for {}, got a chunk of another message",
+                partial.topic
+            );
+            return None;
+        }
+        if offset != partial.data.len() {
+            error!(
+                "Dropping MQTT message for {}: got chunk at {} after {} bytes",
+                partial.topic,
+                offset,
+                partial.data.len()
+            );
+            return None;
+        }
+        if data.len() > partial.total - partial.data.len() {
+            error!(
+                "Dropping MQTT message for {}: chunks are over the {} bytes expected",
+                partial.topic, partial.total
+            );
+            return None;
+        }
+
+        partial.data.extend_from_slice(data);
+        if partial.data.len() < partial.total {
+            self.partial = Some(partial);
+            return None;
+        }
+        Some((partial.topic, partial.data))
+    }
+
+    /// Give up on the partial message, if any, as `topic` has started.
+    fn abandon(&mut self, topic: &str) {
+        if let Some(partial) = self.partial.take() {
+            warn!(
+                "Dropping incomplete MQTT message for {}, got start of {topic}",
+                partial.topic
+            );
+        }
+    }
+
+    fn report_oversize(&self, topic: &str, size: usize) {
+        error!(
+            "Dropping MQTT message for {topic}: {size} bytes is over the limit of {} bytes",
+            self.max_size
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_SIZE: usize = 8;
+
+    fn message(topic: &str, data: &[u8]) -> Option<(String, Vec<u8>)> {
+        Some((topic.to_string(), data.to_vec()))
+    }
+
+    #[test]
+    fn test_complete() {
+        let mut reassembler = Reassembler::new(MAX_SIZE);
+        let got = reassembler.add(1, "a/b", Chunk::Complete, b"ON");
+        assert_eq!(got, message("a/b", b"ON"));
+        let got = reassembler.add(2, "a/b", Chunk::Complete, b"");
+        assert_eq!(got, message("a/b", b""));
+    }
+
+    #[test]
+    fn test_in_order_chunks() {
+        let mut reassembler = Reassembler::new(MAX_SIZE);
+        let total = 6;
+        assert_eq!(
+            reassembler.add(1, "a/b", Chunk::Initial { total }, b"abc"),
+            None
+        );
+        assert_eq!(
+            reassembler.add(1, "", Chunk::Subsequent { offset: 3 }, b"de"),
+            None
+        );
+        let got = reassembler.add(1, "", Chunk::Subsequent { offset: 5 }, b"f");
+        assert_eq!(got, message("a/b", b"abcdef"));
+
+        // Ready for the next one.
+        assert_eq!(
+            reassembler.add(2, "c/d", Chunk::Initial { total }, b"ghi"),
+            None
+        );
+        let got = reassembler.add(2, "", Chunk::Subsequent { offset: 3 }, b"jkl");
+        assert_eq!(got, message("c/d", b"ghijkl"));
+    }
+
+    #[test]
+    fn test_out_of_order_chunk() {
+        let mut reassembler = Reassembler::new(MAX_SIZE);
+        let total = 6;
+        assert_eq!(
+            reassembler.add(1, "a/b", Chunk::Initial { total }, b"ab"),
+            None
+        );
+        assert_eq!(
+            reassembler.add(1, "", Chunk::Subsequent { offset: 4 }, b"ef"),
+            None
+        );
+        // Too late, the message has been dropped.
+        assert_eq!(
+            reassembler.add(1, "", Chunk::Subsequent { offset: 2 }, b"cd"),
+            None
+        );
+        assert_eq!(
+            reassembler.add(1, "", Chunk::Subsequent { offset: 4 }, b"ef"),
+            None
+        );
+
+        let got = reassembler.add(2, "c/d", Chunk::Complete, b"ON");
+        assert_eq!(got, message("c/d", b"ON"));
+    }
+
+    #[test]
+    fn test_missing_chunk() {
+        let mut reassembler = Reassembler::new(MAX_SIZE);
+        let total = 6;
+        assert_eq!(
+            reassembler.add(1, "a/b", Chunk::Initial { total }, b"ab"),
+            None
+        );
+        // The rest never arrives before the next message starts.
+        let got = reassembler.add(2, "c/d", Chunk::Initial { total: 2 }, b"xy");
+        assert_eq!(got, message("c/d", b"xy"));
+        assert_eq!(
+            reassembler.add(1, "", Chunk::Subsequent { offset: 2 }, b"cdef"),
+            None
+        );
+
+        assert_eq!(
+            reassembler.add(3, "a/b", Chunk::Initial { total }, b"ab"),
+            None
+        );
+        let got = reassembler.add(4, "c/d", Chunk::Complete, b"ON");
+        assert_eq!(got, message("c/d", b"ON"));
+        assert_eq!(
+            reassembler.add(3, "", Chunk::Subsequent { offset: 2 }, b"cdef"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_new_id_abandons_partial() {
+        let mut reassembler = Reassembler::new(MAX_SIZE);
+        let total = 4;
+        assert_eq!(
+            reassembler.add(1, "a/b", Chunk::Initial { total }, b"ab"),
+            None
+        );
+        assert_eq!(
+            reassembler.add(2, "", Chunk::Subsequent { offset: 2 }, b"cd"),
+            None
+        );
+        assert_eq!(
+            reassembler.add(1, "", Chunk::Subsequent { offset: 2 }, b"cd"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_too_much_data() {
+        let mut reassembler = Reassembler::new(MAX_SIZE);
+        let total = 4;
+        assert_eq!(
+            reassembler.add(1, "a/b", Chunk::Initial { total }, b"ab"),
+            None
+        );
+        assert_eq!(
+            reassembler.add(1, "", Chunk::Subsequent { offset: 2 }, b"cde"),
+            None
+        );
+        assert_eq!(
+            reassembler.add(1, "a/b", Chunk::Initial { total }, b"abcde"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_oversize_dropped() {
+        let mut reassembler = Reassembler::new(MAX_SIZE);
+        assert_eq!(
+            reassembler.add(1, "a/b", Chunk::Complete, b"123456789"),
+            None
+        );
+        let got = reassembler.add(2, "a/b", Chunk::Complete, b"12345678");
+        assert_eq!(got, message("a/b", b"12345678"));
+
+        // Would fail to allocate, if it tried.
+        let total = usize::MAX;
+        assert_eq!(
+            reassembler.add(3, "a/b", Chunk::Initial { total }, b"1234"),
+            None
+        );
+        assert_eq!(
+            reassembler.add(3, "", Chunk::Subsequent { offset: 4 }, b"5678"),
+            None
+        );
+
+        let total = MAX_SIZE + 1;
+        assert_eq!(
+            reassembler.add(4, "a/b", Chunk::Initial { total }, b"1234"),
+            None
+        );
+        assert_eq!(
+            reassembler.add(4, "", Chunk::Subsequent { offset: 4 }, b"56789"),
+            None
+        );
+
+        let total = MAX_SIZE;
+        assert_eq!(
+            reassembler.add(5, "a/b", Chunk::Initial { total }, b"1234"),
+            None
+        );
+        let got = reassembler.add(5, "", Chunk::Subsequent { offset: 4 }, b"5678");
+        assert_eq!(got, message("a/b", b"12345678"));
+    }
+}
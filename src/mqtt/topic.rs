@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+/// Does the MQTT topic filter `filter` match the concrete topic `topic`?
+///
+/// `+` matches exactly one level and `#` matches the remaining levels,
+/// including none. As per the MQTT spec, topics starting with `$` are not
+/// matched by a leading wildcard.
+pub fn matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(f), Some(t)) if f == t => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Topic filters arranged by level, for finding every filter that matches a
+/// topic without trying each one in turn.
+#[derive(Debug, Eq, PartialEq)]
+pub struct FilterTree<T> {
+    values: Vec<T>,
+    children: HashMap<String, FilterTree<T>>,
+}
+
+impl<T> FilterTree<T> {
+    pub fn new() -> Self {
+        FilterTree {
+            values: Vec::new(),
+            children: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, filter: &str, value: T) {
+        let node = filter.split('/').fold(self, |node, level| {
+            node.children
+                .entry(level.to_string())
+                .or_insert_with(FilterTree::new)
+        });
+        node.values.push(value);
+    }
+
+    /// Get the values for every filter that matches `topic`, with the same
+    /// rules as [`matches`].
+    pub fn get_matches(&self, topic: &str) -> Vec<&T> {
+        let levels: Vec<&str> = topic.split('/').collect();
+        let mut found = Vec::new();
+        self.find(&levels, !topic.starts_with('$'), &mut found);
+        found
+    }
+
+    fn find<'a>(&'a self, levels: &[&str], wildcards: bool, found: &mut Vec<&'a T>) {
+        if wildcards {
+            if let Some(node) = self.children.get("#") {
+                found.extend(node.values.iter());
+            }
+        }
+
+        let Some((level, rest)) = levels.split_first() else {
+            found.extend(self.values.iter());
+            return;
+        };
+
+        if let Some(node) = self.children.get(*level) {
+            node.find(rest, true, found);
+        }
+        if wildcards {
+            if let Some(node) = self.children.get("+") {
+                node.find(rest, true, found);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Filter, topic, and whether they match.
+    const CASES: &[(&str, &str, bool)] = &[
+        ("a/b/c", "a/b/c", true),
+        ("a/b/c", "a/b", false),
+        ("a/b", "a/b/c", false),
+        ("a/b/c", "a/x/c", false),
+        // `+` at the root, in the middle and at the end.
+        ("+/b/c", "a/b/c", true),
+        ("+/b/c", "b/c", false),
+        ("a/+/c", "a/b/c", true),
+        ("a/+/c", "a/b/x/c", false),
+        ("a/b/+", "a/b/c", true),
+        ("a/b/+", "a/b", false),
+        ("a/b/+", "a/b/", true),
+        // `#` at the root, and at the end.
+        ("#", "a/b/c", true),
+        ("#", "a", true),
+        ("a/#", "a/b/c", true),
+        ("a/b/#", "a/x/c", false),
+        // `#` matches the parent level too.
+        ("a/#", "a", true),
+        ("a/b/#", "a/b", true),
+        ("a/b/#", "a", false),
+        ("+/#", "a", true),
+        // Leading wildcards do not match `$` topics.
+        ("#", "$SYS/uptime", false),
+        ("+/uptime", "$SYS/uptime", false),
+        ("$SYS/#", "$SYS/uptime", true),
+        ("$SYS/+", "$SYS/uptime", true),
+        ("a/#", "a/$b", true),
+    ];
+
+    #[test]
+    fn test_matches() {
+        for (filter, topic, expected) in CASES {
+            assert_eq!(
+                matches(filter, topic),
+                *expected,
+                "{filter} matching {topic}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_matches() {
+        for (filter, topic, expected) in CASES {
+            let mut tree = FilterTree::new();
+            tree.insert(filter, ());
+            assert_eq!(
+                tree.get_matches(topic).len(),
+                usize::from(*expected),
+                "{filter} matching {topic}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_matches_overlapping() {
+        let mut tree = FilterTree::new();
+        tree.insert("a/b/c", 1);
+        tree.insert("a/+/c", 2);
+        tree.insert("a/#", 3);
+        tree.insert("#", 4);
+        tree.insert("a/b/c", 5);
+        tree.insert("x/#", 6);
+
+        let mut found = tree.get_matches("a/b/c");
+        found.sort();
+        assert_eq!(found, vec![&1, &2, &3, &4, &5]);
+
+        let mut found = tree.get_matches("a");
+        found.sort();
+        assert_eq!(found, vec![&3, &4]);
+
+        assert_eq!(tree.get_matches("$SYS/a"), Vec::<&i32>::new());
+    }
+}
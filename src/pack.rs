@@ -0,0 +1,320 @@
+//! Icon packs, which hold every image for a set of icons in one blob so they
+//! can live in the `assets` flash partition rather than in the app.
+//!
+//! A pack is a header, an index with an entry for each icon, and then the
+//! images. Numbers are little endian.
+//!
+//! - Header: `RRIP`, the version as a u16 and the number of icons as a u16.
+//! - Index entry: the name, padded with zeros to 24 bytes, then the offset
+//!   and length of each image as u32s, in the order of [`STATES`].
+//! - Images: TGA files, at the offsets given in the index.
+
+#[cfg(not(target_os = "espidf"))]
+use std::collections::BTreeMap;
+#[cfg(not(target_os = "espidf"))]
+use std::fs;
+#[cfg(not(target_os = "espidf"))]
+use std::path::Path;
+
+use anyhow::bail;
+use anyhow::Result;
+
+const MAGIC: &[u8; 4] = b"RRIP";
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 8;
+const NAME_SIZE: usize = 24;
+const ENTRY_SIZE: usize = NAME_SIZE + STATES.len() * 8;
+
+/// Every icon has an image for each of these.
+pub const STATES: [&str; 4] = ["on", "off", "auto", "error"];
+
+pub struct Pack<'a> {
+    data: &'a [u8],
+    count: usize,
+}
+
+impl<'a> Pack<'a> {
+    /// Check the index of a pack, so that looking up images cannot fail.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE || &data[..4] != MAGIC {
+            bail!("Not an icon pack");
+        }
+        let version = read_u16(data, 4);
+        if version != VERSION {
+            bail!("Icon pack version {version} is not supported");
+        }
+
+        let count = read_u16(data, 6) as usize;
+        if data.len() < HEADER_SIZE + count * ENTRY_SIZE {
+            bail!("Icon pack index is truncated");
+        }
+
+        let pack = Pack { data, count };
+        for index in 0..count {
+            for state in 0..STATES.len() {
+                let (offset, len) = pack.get_location(index, state);
+                if offset.saturating_add(len) > data.len() {
+                    bail!("Icon {} is truncated", pack.get_name(index));
+                }
+            }
+        }
+        Ok(pack)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'a str> + '_ {
+        (0..self.count).map(|index| self.get_name(index))
+    }
+
+    /// The TGA image for `state` of the icon called `name`.
+    pub fn get(&self, name: &str, state: &str) -> Option<&'a [u8]> {
+        let state = STATES.iter().position(|s| *s == state)?;
+        let index = (0..self.count).find(|index| self.get_name(*index) == name)?;
+        let (offset, len) = self.get_location(index, state);
+        Some(&self.data[offset..offset + len])
+    }
+
+    fn get_entry(&self, index: usize) -> &'a [u8] {
+        let start = HEADER_SIZE + index * ENTRY_SIZE;
+        &self.data[start..start + ENTRY_SIZE]
+    }
+
+    fn get_name(&self, index: usize) -> &'a str {
+        let name = &self.get_entry(index)[..NAME_SIZE];
+        let len = name.iter().position(|b| *b == 0).unwrap_or(NAME_SIZE);
+        std::str::from_utf8(&name[..len]).unwrap_or("")
+    }
+
+    fn get_location(&self, index: usize, state: usize) -> (usize, usize) {
+        let entry = self.get_entry(index);
+        let at = NAME_SIZE + state * 8;
+        (
+            read_u32(entry, at) as usize,
+            read_u32(entry, at + 4) as usize,
+        )
+    }
+}
+
+fn read_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+/// Build a pack from the images for each icon, in the order of [`STATES`].
+#[cfg(not(target_os = "espidf"))]
+pub fn build(icons: &BTreeMap<String, [Vec<u8>; STATES.len()]>) -> Result<Vec<u8>> {
+    let Ok(count) = u16::try_from(icons.len()) else {
+        bail!("Too many icons for one pack");
+    };
+
+    let mut data = Vec::new();
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&count.to_le_bytes());
+
+    let mut offset = HEADER_SIZE + icons.len() * ENTRY_SIZE;
+    for (name, images) in icons {
+        if name.is_empty() || name.len() >= NAME_SIZE {
+            bail!("Icon name {name} must be 1 to {} bytes", NAME_SIZE - 1);
+        }
+        let mut padded = [0; NAME_SIZE];
+        padded[..name.len()].copy_from_slice(name.as_bytes());
+        data.extend_from_slice(&padded);
+
+        for image in images {
+            data.extend_from_slice(&(offset as u32).to_le_bytes());
+            data.extend_from_slice(&(image.len() as u32).to_le_bytes());
+            offset += image.len();
+        }
+    }
+
+    for images in icons.values() {
+        for image in images {
+            data.extend_from_slice(image);
+        }
+    }
+    Ok(data)
+}
+
+/// Names are used in the config, so keep them easy to type.
+#[cfg(not(target_os = "espidf"))]
+fn is_valid_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Build a pack from every `<name>_<state>_64x64.tga` in `dir`. Each icon
+/// needs an image for every state, and a name of lower case letters, digits
+/// and underscores.
+#[cfg(not(target_os = "espidf"))]
+pub fn build_from_dir(dir: &Path) -> Result<Vec<u8>> {
+    let mut found: BTreeMap<String, BTreeMap<String, Vec<u8>>> = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().and_then(|name| name.to_str());
+        let Some(stem) = file_name.and_then(|name| name.strip_suffix("_64x64.tga")) else {
+            continue;
+        };
+
+        let Some((name, state)) = stem.rsplit_once('_') else {
+            bail!(
+                "{} should be named <name>_<state>_64x64.tga",
+                path.display()
+            );
+        };
+        if !STATES.contains(&state) {
+            bail!(
+                "{} is for unknown state {state}, expected one of {STATES:?}",
+                path.display()
+            );
+        }
+        if !is_valid_name(name) {
+            bail!(
+                "{}: icon names must be lower case letters, digits and underscores",
+                path.display()
+            );
+        }
+
+        found
+            .entry(name.to_string())
+            .or_default()
+            .insert(state.to_string(), fs::read(&path)?);
+    }
+
+    if found.is_empty() {
+        bail!("No icons found in {}", dir.display());
+    }
+
+    let mut icons = BTreeMap::new();
+    for (name, mut images) in found {
+        let mut take = |state: &str| match images.remove(state) {
+            Some(image) => Ok(image),
+            None => Err(anyhow::anyhow!(
+                "Icon {name} has no image for {state}, add {name}_{state}_64x64.tga"
+            )),
+        };
+        let [on, off, auto, error] = STATES.map(&mut take);
+        icons.insert(name, [on?, off?, auto?, error?]);
+    }
+    build(&icons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn icons() -> BTreeMap<String, [Vec<u8>; STATES.len()]> {
+        let mut icons = BTreeMap::new();
+        icons.insert(
+            "light".to_string(),
+            [vec![1, 2], vec![3], vec![], vec![4, 5, 6]],
+        );
+        icons.insert("fan".to_string(), [vec![7], vec![8], vec![9], vec![10]]);
+        icons
+    }
+
+    fn parse_err(data: &[u8]) -> String {
+        match Pack::parse(data) {
+            Ok(_) => panic!("Parsed a bad pack"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    /// Where the offset of the image for `state` of icon `index` is.
+    fn location_at(index: usize, state: usize) -> usize {
+        HEADER_SIZE + index * ENTRY_SIZE + NAME_SIZE + state * 8
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let icons = icons();
+        let data = build(&icons).unwrap();
+        let pack = Pack::parse(&data).unwrap();
+
+        assert_eq!(pack.names().collect::<Vec<_>>(), vec!["fan", "light"]);
+        for (name, images) in &icons {
+            for (state, image) in STATES.iter().zip(images) {
+                assert_eq!(pack.get(name, state), Some(image.as_slice()));
+            }
+        }
+        assert_eq!(pack.get("door", "on"), None);
+        assert_eq!(pack.get("fan", "dim"), None);
+    }
+
+    #[test]
+    fn test_empty_pack() {
+        let data = build(&BTreeMap::new()).unwrap();
+        let pack = Pack::parse(&data).unwrap();
+        assert_eq!(pack.names().count(), 0);
+    }
+
+    #[test]
+    fn test_build_bad_name() {
+        for name in ["", "a_name_far_too_long_for_a_pack"] {
+            let mut icons = icons();
+            icons.insert(name.to_string(), Default::default());
+            assert!(build(&icons).is_err(), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let data = build(&icons()).unwrap();
+
+        assert_eq!(parse_err(&[]), "Not an icon pack");
+        assert_eq!(parse_err(&data[..HEADER_SIZE - 1]), "Not an icon pack");
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(parse_err(&bad_magic), "Not an icon pack");
+
+        let mut bad_version = data;
+        bad_version[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert_eq!(
+            parse_err(&bad_version),
+            "Icon pack version 2 is not supported"
+        );
+    }
+
+    #[test]
+    fn test_truncated_index() {
+        let data = build(&icons()).unwrap();
+        for len in [HEADER_SIZE, HEADER_SIZE + ENTRY_SIZE, location_at(1, 3)] {
+            assert_eq!(parse_err(&data[..len]), "Icon pack index is truncated");
+        }
+
+        let mut too_many = data;
+        too_many[6..8].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_eq!(parse_err(&too_many), "Icon pack index is truncated");
+    }
+
+    #[test]
+    fn test_image_past_end() {
+        let data = build(&icons()).unwrap();
+        assert_eq!(
+            parse_err(&data[..data.len() - 1]),
+            "Icon light is truncated"
+        );
+
+        let end = data.len() as u32;
+        let cases = [
+            // Offset past the end.
+            (end, 1),
+            // Length past the end.
+            (0, end + 1),
+            // Offset and length that overflow when added.
+            (u32::MAX, u32::MAX),
+        ];
+        for (offset, len) in cases {
+            let at = location_at(0, 1);
+            let mut bad = data.clone();
+            bad[at..at + 4].copy_from_slice(&offset.to_le_bytes());
+            bad[at + 4..at + 8].copy_from_slice(&len.to_le_bytes());
+            assert_eq!(parse_err(&bad), "Icon fan is truncated", "{offset} {len}");
+        }
+    }
+}
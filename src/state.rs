@@ -0,0 +1,1308 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::*;
+use robotica_common::controllers::DisplayState;
+use robotica_common::mqtt::MqttMessage;
+use serde::Serialize;
+
+use crate::button::ButtonId;
+use crate::command::CommandReply;
+use crate::command::RemoteCommand;
+use crate::config;
+use crate::config::BlankCondition;
+use crate::config::Combine;
+use crate::config::Config;
+use crate::config::ConfigAck;
+use crate::config::Controller;
+use crate::config::OfflinePolicy;
+use crate::config::Page;
+use crate::config::PressWhileBlank;
+use crate::discovery;
+use crate::display::DisplayCommand;
+use crate::messages::Message;
+use crate::mqtt;
+use crate::mqtt::ConnectionState;
+use crate::mqtt::Decoding;
+use crate::mqtt::Payload;
+use crate::mqtt::Subscriptions;
+use crate::supervisor::Failure;
+
+/// Something the ESP glue needs to do as a result of a message.
+#[derive(Debug, PartialEq)]
+pub enum Effect {
+    Display(DisplayCommand),
+    Publish {
+        topic: String,
+        retain: bool,
+        payload: String,
+    },
+    StartBlankTimer(Duration),
+    CancelBlankTimer,
+    Subscribe(Subscriptions),
+    StoreConfig(String),
+    Restart,
+    StartTelemetryTimer(Duration),
+    CancelTelemetryTimer,
+    PublishTelemetry {
+        topic: String,
+        mqtt_reconnects: u32,
+    },
+    SetTimezone(String),
+    /// Send the time and signal strength to the status bar.
+    UpdateStatus,
+}
+
+const MAX_QUEUED_PRESSES: usize = 16;
+
+/// Keep only the most recent failures while we cannot report them.
+const MAX_UNREPORTED_FAILURES: usize = 8;
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// Retained on the device info topic each time we connect.
+#[derive(Serialize)]
+struct DeviceInfo<'a> {
+    unique_id: &'a str,
+    version: &'a str,
+}
+
+/// A press made while disconnected, waiting to be sent.
+struct QueuedPress {
+    controller: usize,
+    commands: Vec<MqttMessage>,
+    time: Instant,
+}
+
+struct RequestedDisplayStatus {
+    /// Whether each blanking condition is met, starting from its
+    /// `initially_met` until something is received for it.
+    conditions: Vec<bool>,
+    wake_timer: bool,
+}
+
+struct ActualDisplayStatus {
+    timer_on: bool,
+    display_on: bool,
+}
+
+impl RequestedDisplayStatus {
+    fn new(conditions: &[BlankCondition]) -> Self {
+        let mut status = RequestedDisplayStatus {
+            conditions: Vec::new(),
+            wake_timer: false,
+        };
+        status.reset_conditions(conditions);
+        status
+    }
+
+    /// Forget what has been received for the conditions.
+    fn reset_conditions(&mut self, conditions: &[BlankCondition]) {
+        self.conditions = conditions.iter().map(|c| c.initially_met).collect();
+    }
+
+    fn get_timer_required(&self) -> bool {
+        self.wake_timer
+    }
+
+    fn get_display_required(&self, combine: Combine) -> bool {
+        let mut conditions = self.conditions.iter();
+        let conditions_met = match combine {
+            Combine::Any => conditions.any(|c| *c),
+            Combine::All => conditions.all(|c| *c),
+        };
+        conditions_met || self.wake_timer
+    }
+
+    fn turn_wake_timer_on(&mut self) {
+        self.wake_timer = true;
+    }
+
+    fn turn_wake_timer_off(&mut self) {
+        self.wake_timer = false;
+    }
+}
+
+/// Everything the remote knows, without any knowledge of the hardware.
+///
+/// Messages go in, and a list of effects for the caller to carry out comes
+/// out.
+pub struct RemoteState {
+    unique_id: String,
+    config: Config,
+    controllers: Vec<Controller>,
+    pages: Vec<Page>,
+    num_per_page: usize,
+    page_num: usize,
+    requested_display_status: RequestedDisplayStatus,
+    status: ActualDisplayStatus,
+    showing_error: bool,
+    connected: bool,
+    connection: ConnectionState,
+    num_connects: u32,
+    queue: VecDeque<QueuedPress>,
+    failures: VecDeque<Failure>,
+}
+
+impl RemoteState {
+    pub fn new(config: Config, num_per_page: usize, unique_id: &str) -> Self {
+        let (controllers, pages) = config.create_controllers();
+        let requested_display_status = RequestedDisplayStatus::new(&config.blanking.conditions);
+        RemoteState {
+            unique_id: unique_id.to_string(),
+            controllers,
+            pages,
+            config,
+            num_per_page,
+            page_num: 0,
+            requested_display_status,
+            status: ActualDisplayStatus {
+                display_on: true,
+                timer_on: false,
+            },
+            showing_error: false,
+            connected: false,
+            connection: ConnectionState::Connecting,
+            num_connects: 0,
+            queue: VecDeque::new(),
+            failures: VecDeque::new(),
+        }
+    }
+
+    pub fn start(&mut self) -> Vec<Effect> {
+        let mut effects = Vec::new();
+        // Show what we have on boot, even if the conditions say otherwise.
+        self.requested_display_status.turn_wake_timer_on();
+        effects.push(Effect::Display(DisplayCommand::SetBrightness(
+            self.config.blanking.brightness,
+        )));
+        self.do_blank(&mut effects, false);
+        effects.push(Effect::Display(DisplayCommand::Settings(
+            self.config.display.clone(),
+        )));
+        effects.push(Effect::Display(DisplayCommand::Started));
+        effects.push(Effect::Display(DisplayCommand::Connection(
+            self.connection.clone(),
+        )));
+        self.show_page(&mut effects);
+        self.start_telemetry_timer(&mut effects);
+        effects.push(Effect::SetTimezone(self.config.clock.timezone.clone()));
+        effects.push(Effect::UpdateStatus);
+        effects
+    }
+
+    pub fn get_subscriptions(&self) -> Subscriptions {
+        let mut subscriptions = Subscriptions::new();
+        for (index, f) in self.controllers.iter().enumerate() {
+            let new_subscriptions = f.get_subscriptions();
+            for s in new_subscriptions {
+                let label = mqtt::Label::Button(index, s.label);
+                info!("Subscribing to {}.", s.topic);
+                subscriptions.add(&s.topic, label, Decoding::Text);
+            }
+        }
+
+        for (index, condition) in self.config.blanking.conditions.iter().enumerate() {
+            let label = mqtt::Label::BlankCondition(index);
+            subscriptions.add(&condition.topic, label, condition.decoding);
+        }
+        subscriptions.add(
+            &config::get_config_topic(&self.unique_id),
+            mqtt::Label::Config,
+            Decoding::Text,
+        );
+        // Text rather than JSON, so bad commands still get a reply.
+        subscriptions.add(
+            &config::get_command_topic(&self.unique_id),
+            mqtt::Label::Command,
+            Decoding::Text,
+        );
+        subscriptions
+    }
+
+    /// The will to register with the broker, so we are marked as offline if
+    /// we drop off.
+    pub fn get_will(&self) -> mqtt::Will {
+        mqtt::Will {
+            topic: config::get_availability_topic(&self.unique_id),
+            payload: OFFLINE.to_string(),
+            retain: true,
+        }
+    }
+
+    pub fn handle(&mut self, message: Message) -> Vec<Effect> {
+        let mut effects = Vec::new();
+
+        match message {
+            Message::MqttReceived(topic, payload, mqtt::Label::BlankCondition(index)) => {
+                info!("Got blank condition {index}: {} - {:?}", topic, payload);
+                let condition = self.config.blanking.conditions.get(index);
+                let status = self.requested_display_status.conditions.get_mut(index);
+                if let (Some(condition), Some(status)) = (condition, status) {
+                    *status = condition.matches(&payload);
+                }
+                self.do_blank(&mut effects, false);
+            }
+            Message::MqttReceived(topic, Payload::Text(data), mqtt::Label::Button(id, sid)) => {
+                info!("Got message for button {id}/{sid}: {} - {}", topic, data);
+                let Some(controller) = self.controllers.get_mut(id) else {
+                    error!("Controller for button {} does not exist", id);
+                    return effects;
+                };
+                let old_state = controller.get_display_state();
+                controller.process_message(sid, data);
+                let state = controller.get_display_state();
+                info!("State changed: {} - {:?} -> {:?}", topic, old_state, state);
+
+                if let Some(id_in_page) = self.controller_to_id_in_page(id) {
+                    if old_state != state {
+                        let controller = &self.controllers[id];
+                        let queued = self.is_queued(id);
+                        update_display(&mut effects, id_in_page, controller, state, queued);
+                    }
+                }
+            }
+            Message::MqttReceived(_, Payload::Text(data), mqtt::Label::Config) => {
+                self.process_config(&mut effects, data);
+            }
+            Message::MqttReceived(_, Payload::Text(data), mqtt::Label::Command) => {
+                self.process_command(&mut effects, &data);
+            }
+            Message::MqttReceived(topic, payload, label) => {
+                error!("Unexpected payload for {label:?} on {topic}: {payload:?}");
+            }
+            Message::MqttConnecting => {
+                info!("Got connecting");
+                // Keep showing the failure until it works.
+                if self.connection != ConnectionState::AuthFailed {
+                    self.set_connection(&mut effects, ConnectionState::Connecting);
+                }
+            }
+            Message::MqttAuthFailed => {
+                error!("Got MQTT auth failure");
+                self.set_connection(&mut effects, ConnectionState::AuthFailed);
+            }
+            Message::MqttConnect => {
+                info!("Got connected");
+                self.set_connection(&mut effects, ConnectionState::Connected);
+                if self.showing_error {
+                    self.showing_error = false;
+                    effects.push(Effect::Display(DisplayCommand::Started));
+                    self.show_page(&mut effects);
+                }
+                self.connected = true;
+                self.num_connects = self.num_connects.saturating_add(1);
+                self.announce(&mut effects);
+                self.replay_queue(&mut effects);
+            }
+            Message::MqttError(error) => {
+                error!("Got MQTT error: {error}");
+                self.showing_error = true;
+                effects.push(Effect::Display(DisplayCommand::Error(error)));
+            }
+            Message::MqttDisconnect => {
+                info!("Got disconnected");
+                self.connected = false;
+                if self.connection != ConnectionState::AuthFailed {
+                    self.set_connection(&mut effects, ConnectionState::Reconnecting);
+                }
+                for controller in self.controllers.iter_mut() {
+                    controller.process_disconnected();
+                }
+                self.update_displays(&mut effects);
+            }
+            Message::ButtonPress(ButtonId::Physical(id_in_page)) => {
+                self.publish_button(&mut effects, id_in_page);
+                let act = match self.config.blanking.press_while_blank {
+                    PressWhileBlank::WakeOnly => self.status.display_on,
+                    PressWhileBlank::WakeAndAct => true,
+                };
+                if act {
+                    match self.page_to_controller_id(self.page_num, id_in_page) {
+                        Some(id) => self.button_press(&mut effects, id),
+                        None => info!("No controller for button {}", id_in_page),
+                    }
+                    effects.push(Effect::Display(DisplayCommand::ButtonPressed(id_in_page)));
+                }
+                self.requested_display_status.turn_wake_timer_on();
+                self.do_blank(&mut effects, true);
+            }
+            Message::ButtonPress(ButtonId::Controller(id)) => {
+                self.button_press(&mut effects, id);
+                if let Some(id_in_page) = self.controller_to_id_in_page(id) {
+                    effects.push(Effect::Display(DisplayCommand::ButtonPressed(id_in_page)));
+                }
+                self.requested_display_status.turn_wake_timer_on();
+                self.do_blank(&mut effects, true);
+            }
+            Message::ButtonPress(ButtonId::PageUp) => {
+                info!("got page up");
+                let last_page = self.get_num_pages().saturating_sub(1);
+                self.page_num = self.page_num.saturating_add(1);
+                if self.page_num > last_page {
+                    self.page_num = last_page
+                };
+                self.show_page(&mut effects);
+                self.requested_display_status.turn_wake_timer_on();
+                self.do_blank(&mut effects, true);
+            }
+            Message::ButtonPress(ButtonId::PageDown) => {
+                info!("got page down");
+                self.page_num = self.page_num.saturating_sub(1);
+                self.show_page(&mut effects);
+                self.requested_display_status.turn_wake_timer_on();
+                self.do_blank(&mut effects, true);
+            }
+            Message::ButtonPress(ButtonId::NotAButton) => {
+                info!("Got not a button press");
+                self.requested_display_status.turn_wake_timer_on();
+                self.do_blank(&mut effects, true);
+            }
+            Message::ButtonRelease(ButtonId::Physical(id_in_page)) => {
+                info!("Got button release");
+                effects.push(Effect::Display(DisplayCommand::ButtonReleased(id_in_page)));
+                self.requested_display_status.turn_wake_timer_on();
+                self.do_blank(&mut effects, true);
+            }
+            Message::ButtonRelease(ButtonId::Controller(id)) => {
+                info!("Got button release");
+                if let Some(id_in_page) = self.controller_to_id_in_page(id) {
+                    effects.push(Effect::Display(DisplayCommand::ButtonReleased(id_in_page)));
+                }
+                self.requested_display_status.turn_wake_timer_on();
+                self.do_blank(&mut effects, true);
+            }
+            Message::ButtonRelease(_) => {
+                info!("Got button release");
+                self.requested_display_status.turn_wake_timer_on();
+                self.do_blank(&mut effects, true);
+            }
+            Message::Telemetry => {
+                if self.connected {
+                    effects.push(Effect::PublishTelemetry {
+                        topic: config::get_telemetry_topic(&self.unique_id),
+                        mqtt_reconnects: self.num_connects.saturating_sub(1),
+                    });
+                }
+            }
+            Message::Clock => {
+                // Catches up when the display is turned on.
+                let shown =
+                    self.config.blanking.dim_brightness > 0 || self.config.display.screensaver;
+                if self.status.display_on || shown {
+                    effects.push(Effect::UpdateStatus);
+                }
+            }
+            Message::BlankDisplays => {
+                info!("Got blank display timer");
+                self.requested_display_status.turn_wake_timer_off();
+                self.do_blank(&mut effects, true);
+            }
+            Message::WorkerFailed(failure) => {
+                if self.failures.len() >= MAX_UNREPORTED_FAILURES {
+                    self.failures.pop_front();
+                }
+                self.failures.push_back(failure);
+                self.report_failures(&mut effects);
+            }
+        }
+
+        effects
+    }
+
+    fn set_connection(&mut self, effects: &mut Vec<Effect>, connection: ConnectionState) {
+        if self.connection != connection {
+            info!("Connection state {:?}", connection);
+            self.connection = connection.clone();
+            effects.push(Effect::Display(DisplayCommand::Connection(connection)));
+        }
+    }
+
+    fn announce(&mut self, effects: &mut Vec<Effect>) {
+        let info = DeviceInfo {
+            unique_id: &self.unique_id,
+            version: env!("CARGO_PKG_VERSION"),
+        };
+        effects.push(Effect::Publish {
+            topic: config::get_availability_topic(&self.unique_id),
+            retain: true,
+            payload: ONLINE.to_string(),
+        });
+        effects.push(Effect::Publish {
+            topic: config::get_device_info_topic(&self.unique_id),
+            retain: true,
+            payload: serde_json::to_string(&info).unwrap(),
+        });
+
+        for (topic, payload) in discovery::get_documents(&self.unique_id, self.num_per_page) {
+            effects.push(Effect::Publish {
+                topic,
+                retain: true,
+                payload,
+            });
+        }
+        self.publish_page(effects);
+        self.publish_display(effects);
+        self.report_failures(effects);
+    }
+
+    /// Publish failures of worker threads, or keep them until we connect.
+    fn report_failures(&mut self, effects: &mut Vec<Effect>) {
+        if self.connected {
+            for failure in self.failures.drain(..) {
+                effects.push(Effect::Publish {
+                    topic: config::get_error_topic(&self.unique_id),
+                    retain: false,
+                    payload: serde_json::to_string(&failure).unwrap(),
+                });
+            }
+        }
+    }
+
+    // These are only published while connected, and again on connecting.
+    fn publish_page(&self, effects: &mut Vec<Effect>) {
+        if self.connected {
+            effects.push(Effect::Publish {
+                topic: config::get_page_topic(&self.unique_id),
+                retain: true,
+                payload: self.get_page_title(),
+            });
+        }
+    }
+
+    fn publish_display(&self, effects: &mut Vec<Effect>) {
+        if self.connected {
+            let payload = if self.status.display_on { "ON" } else { "OFF" };
+            effects.push(Effect::Publish {
+                topic: config::get_display_topic(&self.unique_id),
+                retain: true,
+                payload: payload.to_string(),
+            });
+        }
+    }
+
+    fn publish_button(&self, effects: &mut Vec<Effect>, id_in_page: usize) {
+        if self.connected {
+            effects.push(Effect::Publish {
+                topic: config::get_button_topic(&self.unique_id, id_in_page),
+                retain: false,
+                payload: "press".to_string(),
+            });
+        }
+    }
+
+    fn process_config(&mut self, effects: &mut Vec<Effect>, data: String) {
+        let ack = match config::parse_config(data.as_bytes(), self.num_per_page) {
+            Ok(config) if config == self.config => {
+                info!("Config unchanged");
+                ConfigAck::Accepted
+            }
+            Ok(config) => {
+                info!("Got new config");
+                effects.push(Effect::StoreConfig(data));
+                // The controllers are about to be replaced, so the presses
+                // queued for them are meaningless.
+                self.queue.clear();
+                (self.controllers, self.pages) = config.create_controllers();
+                self.requested_display_status
+                    .reset_conditions(&config.blanking.conditions);
+                let telemetry_changed = config.telemetry != self.config.telemetry;
+                let clock_changed = config.clock != self.config.clock;
+                let display_changed = config.display != self.config.display;
+                let blanking_changed = config.blanking != self.config.blanking;
+                self.config = config;
+                if telemetry_changed {
+                    self.start_telemetry_timer(effects);
+                }
+                if clock_changed {
+                    effects.push(Effect::SetTimezone(self.config.clock.timezone.clone()));
+                    effects.push(Effect::UpdateStatus);
+                }
+                if display_changed {
+                    effects.push(Effect::Display(DisplayCommand::Settings(
+                        self.config.display.clone(),
+                    )));
+                }
+                effects.push(Effect::Subscribe(self.get_subscriptions()));
+                if blanking_changed {
+                    // Show the new brightness for whichever state it is in.
+                    if self.status.display_on {
+                        self.wake_displays(effects);
+                    } else {
+                        self.sleep_displays(effects);
+                    }
+                }
+                self.do_blank(effects, false);
+
+                let last_page = self.get_num_pages().saturating_sub(1);
+                if self.page_num > last_page {
+                    self.page_num = last_page;
+                }
+                self.show_page(effects);
+                ConfigAck::Accepted
+            }
+            Err(err) => {
+                error!("Rejected config: {err}");
+                ConfigAck::Rejected {
+                    reason: err.to_string(),
+                }
+            }
+        };
+
+        effects.push(Effect::Publish {
+            topic: config::get_config_ack_topic(&self.unique_id),
+            retain: false,
+            payload: serde_json::to_string(&ack).unwrap(),
+        });
+    }
+
+    fn start_telemetry_timer(&self, effects: &mut Vec<Effect>) {
+        match self.config.telemetry.interval_secs {
+            0 => effects.push(Effect::CancelTelemetryTimer),
+            secs => effects.push(Effect::StartTelemetryTimer(Duration::from_secs(secs))),
+        }
+    }
+
+    fn process_command(&mut self, effects: &mut Vec<Effect>, data: &str) {
+        let mut restart = false;
+        let result = match serde_json::from_str(data) {
+            Ok(command) => {
+                info!("Got command {command:?}");
+                restart = matches!(command, RemoteCommand::Restart);
+                self.do_command(effects, command)
+            }
+            Err(err) => Err(err.to_string()),
+        };
+
+        let reply = match result {
+            Ok(()) => CommandReply::Ok,
+            Err(reason) => {
+                error!("Command failed: {reason}");
+                CommandReply::Error { reason }
+            }
+        };
+        effects.push(Effect::Publish {
+            topic: config::get_command_reply_topic(&self.unique_id),
+            retain: false,
+            payload: serde_json::to_string(&reply).unwrap(),
+        });
+
+        if restart {
+            effects.push(Effect::Restart);
+        }
+    }
+
+    fn do_command(
+        &mut self,
+        effects: &mut Vec<Effect>,
+        command: RemoteCommand,
+    ) -> Result<(), String> {
+        match command {
+            RemoteCommand::Press { controller } => {
+                if controller >= self.controllers.len() {
+                    return Err(format!("No controller {controller}"));
+                }
+                let id = ButtonId::Controller(controller);
+                effects.extend(self.handle(Message::ButtonPress(id)));
+                effects.extend(self.handle(Message::ButtonRelease(id)));
+            }
+            RemoteCommand::ShowPage { page } => {
+                if page >= self.get_num_pages() {
+                    return Err(format!("No page {page}"));
+                }
+                self.page_num = page;
+                self.show_page(effects);
+            }
+            RemoteCommand::Blank => {
+                self.requested_display_status.turn_wake_timer_off();
+                if self.status.timer_on {
+                    effects.push(Effect::CancelBlankTimer);
+                    self.status.timer_on = false;
+                }
+                if self.status.display_on {
+                    self.status.display_on = false;
+                    self.sleep_displays(effects);
+                    self.publish_display(effects);
+                }
+            }
+            RemoteCommand::UnBlank => {
+                self.requested_display_status.turn_wake_timer_on();
+                self.do_blank(effects, true);
+            }
+            RemoteCommand::Restart => {}
+        }
+        Ok(())
+    }
+
+    fn do_blank(&mut self, effects: &mut Vec<Effect>, force_timer_reset: bool) {
+        let was_on = self.status.display_on;
+        let blanking = &self.config.blanking;
+        let timeout = Duration::from_secs(blanking.timeout_secs);
+        let timer_required = self.requested_display_status.get_timer_required();
+        let display_required = self
+            .requested_display_status
+            .get_display_required(blanking.combine);
+        let status = &mut self.status;
+
+        match (timer_required, status.timer_on) {
+            (true, true) => {
+                if force_timer_reset {
+                    info!("resetting blank timer");
+                    effects.push(Effect::StartBlankTimer(timeout));
+                    status.timer_on = true;
+                }
+            }
+            (true, false) => {
+                info!("starting blank timer");
+                effects.push(Effect::StartBlankTimer(timeout));
+                status.timer_on = true;
+            }
+            (false, true) => {
+                info!("stopping blank timer");
+                effects.push(Effect::CancelBlankTimer);
+                status.timer_on = false;
+            }
+            (false, false) => {}
+        };
+
+        match (display_required, status.display_on) {
+            (true, false) => {
+                info!("turning display on");
+                status.display_on = true;
+                self.wake_displays(effects);
+            }
+            (false, true) => {
+                info!("turning display off");
+                status.display_on = false;
+                self.sleep_displays(effects);
+            }
+            (true, true) => {}
+            (false, false) => {}
+        };
+
+        if self.status.display_on != was_on {
+            self.publish_display(effects);
+        }
+    }
+
+    fn wake_displays(&self, effects: &mut Vec<Effect>) {
+        let brightness = self.config.blanking.brightness;
+        effects.push(Effect::Display(DisplayCommand::SetBrightness(brightness)));
+        effects.push(Effect::Display(DisplayCommand::UnBlankAll));
+        effects.push(Effect::UpdateStatus);
+    }
+
+    /// Dim the displays, or turn them off if there is no dim brightness.
+    fn sleep_displays(&self, effects: &mut Vec<Effect>) {
+        match self.config.blanking.dim_brightness {
+            0 => effects.push(Effect::Display(DisplayCommand::BlankAll)),
+            dim => {
+                effects.push(Effect::Display(DisplayCommand::SetBrightness(dim)));
+                effects.push(Effect::Display(DisplayCommand::UnBlankAll));
+            }
+        }
+    }
+
+    fn button_press(&mut self, effects: &mut Vec<Effect>, id: usize) {
+        info!("Got button {} press", id);
+        let Some(controller) = self.controllers.get(id) else {
+            error!("Controller for button {} does not exist", id);
+            return;
+        };
+
+        let commands = controller.get_press_commands();
+        if self.connected {
+            publish_commands(effects, commands);
+            return;
+        }
+
+        if controller.get_offline_policy() == OfflinePolicy::Drop {
+            warn!(
+                "Dropping press for {} while disconnected",
+                controller.get_name()
+            );
+            return;
+        }
+
+        info!("Queueing press for {}", controller.get_name());
+        if self.queue.len() >= MAX_QUEUED_PRESSES {
+            if let Some(oldest) = self.queue.pop_front() {
+                warn!(
+                    "Press queue full, dropping press for button {}",
+                    oldest.controller
+                );
+                self.update_queued(effects, oldest.controller);
+            }
+        }
+        self.queue.push_back(QueuedPress {
+            controller: id,
+            commands,
+            time: Instant::now(),
+        });
+        self.update_queued(effects, id);
+    }
+
+    fn replay_queue(&mut self, effects: &mut Vec<Effect>) {
+        let queue = std::mem::take(&mut self.queue);
+        for press in queue {
+            let controller = &self.controllers[press.controller];
+            let replay = match controller.get_offline_policy() {
+                OfflinePolicy::Replay => true,
+                OfflinePolicy::ReplayIfYounger { max_age_secs } => {
+                    press.time.elapsed() < Duration::from_secs(max_age_secs)
+                }
+                OfflinePolicy::Drop => false,
+            };
+
+            if replay {
+                info!("Replaying press for {}", controller.get_name());
+                publish_commands(effects, press.commands);
+            } else {
+                warn!("Dropping stale press for {}", controller.get_name());
+            }
+            self.update_queued(effects, press.controller);
+        }
+    }
+
+    fn is_queued(&self, id: usize) -> bool {
+        self.queue.iter().any(|press| press.controller == id)
+    }
+
+    fn update_queued(&self, effects: &mut Vec<Effect>, id: usize) {
+        if let Some(id_in_page) = self.controller_to_id_in_page(id) {
+            let queued = self.is_queued(id);
+            effects.push(Effect::Display(DisplayCommand::ButtonQueued(
+                id_in_page, queued,
+            )));
+        }
+    }
+
+    fn show_page(&self, effects: &mut Vec<Effect>) {
+        let title = self
+            .pages
+            .get(self.page_num)
+            .map(|page| page.title.clone())
+            .unwrap_or_default();
+        effects.push(Effect::Display(DisplayCommand::ShowPage(
+            self.page_num,
+            self.get_num_pages(),
+            title,
+        )));
+        self.update_displays(effects);
+    }
+
+    fn update_displays(&self, effects: &mut Vec<Effect>) {
+        for id_in_page in 0..self.num_per_page {
+            if let Some(id) = self.page_to_controller_id(self.page_num, id_in_page) {
+                let controller = &self.controllers[id];
+                let state = controller.get_display_state();
+                update_display(effects, id_in_page, controller, state, self.is_queued(id));
+            } else {
+                let message = DisplayCommand::DisplayNone(id_in_page);
+                effects.push(Effect::Display(message));
+            };
+        }
+    }
+
+    /// Where is this controller on the current page, if it is on it?
+    fn controller_to_id_in_page(&self, controller_id: usize) -> Option<usize> {
+        let page = self.pages.get(self.page_num)?;
+        page.slots.iter().position(|s| *s == Some(controller_id))
+    }
+
+    fn page_to_controller_id(&self, page_num: usize, id_in_page: usize) -> Option<usize> {
+        let page = self.pages.get(page_num)?;
+        page.slots.get(id_in_page).copied().flatten()
+    }
+
+    fn get_num_pages(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+fn update_display(
+    effects: &mut Vec<Effect>,
+    id_in_page: usize,
+    controller: &Controller,
+    state: DisplayState,
+    queued: bool,
+) {
+    let name = controller.get_name().to_string();
+    let icon = controller.get_icon();
+    let message = DisplayCommand::DisplayState(state, icon, id_in_page, name);
+    effects.push(Effect::Display(message));
+    if queued {
+        effects.push(Effect::Display(DisplayCommand::ButtonQueued(
+            id_in_page, true,
+        )));
+    }
+}
+
+fn publish_commands(effects: &mut Vec<Effect>, commands: Vec<MqttMessage>) {
+    for command in commands {
+        let topic = command.topic;
+        let payload = command.payload;
+        info!("Send {}: {}", topic, payload);
+        effects.push(Effect::Publish {
+            topic,
+            retain: false,
+            payload,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PER_PAGE: usize = 2;
+
+    /// Pages of two: "On" and "Auto", then "Brian Fan" and "Passage", then
+    /// "Brian Wake-Up" and "TV".
+    fn default_config() -> Config {
+        Config::default_for(PER_PAGE)
+    }
+
+    fn start(config: Config) -> RemoteState {
+        let mut state = RemoteState::new(config, PER_PAGE, "test");
+        state.start();
+        state
+    }
+
+    fn condition(index: usize, payload: &str) -> Message {
+        let topic = format!("condition/{index}");
+        let payload = Payload::Text(payload.to_string());
+        Message::MqttReceived(topic, payload, mqtt::Label::BlankCondition(index))
+    }
+
+    fn shown_page(effects: &[Effect]) -> Option<(usize, usize, String)> {
+        effects.iter().rev().find_map(|effect| match effect {
+            Effect::Display(DisplayCommand::ShowPage(page, num_pages, title)) => {
+                Some((*page, *num_pages, title.clone()))
+            }
+            _ => None,
+        })
+    }
+
+    fn published(effects: &[Effect]) -> Vec<(String, String)> {
+        effects
+            .iter()
+            .filter_map(|effect| match effect {
+                Effect::Publish { topic, payload, .. } => Some((topic.clone(), payload.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn press_commands(config: &Config, page: usize, slot: usize) -> Vec<(String, String)> {
+        let controller = config.pages[page].slots[slot].as_ref().unwrap();
+        let commands = controller.create_controller().get_press_commands();
+        assert!(!commands.is_empty());
+        commands.into_iter().map(|c| (c.topic, c.payload)).collect()
+    }
+
+    fn has(effects: &[Effect], command: DisplayCommand) -> bool {
+        effects.contains(&Effect::Display(command))
+    }
+
+    #[test]
+    fn test_blank_conditions() {
+        // The default conditions are met by "OFF" and "ON" respectively.
+        for combine in [Combine::Any, Combine::All] {
+            for (met0, met1) in [(false, false), (false, true), (true, false), (true, true)] {
+                let mut config = default_config();
+                config.blanking.combine = combine;
+                for condition in &mut config.blanking.conditions {
+                    condition.initially_met = true;
+                }
+                let mut state = start(config);
+
+                // Still on, as both are met until something is received.
+                let effects = state.handle(Message::BlankDisplays);
+                assert!(state.status.display_on);
+                assert!(!has(&effects, DisplayCommand::BlankAll));
+
+                let mut effects = state.handle(condition(0, if met0 { "OFF" } else { "ON" }));
+                effects.extend(state.handle(condition(1, if met1 { "ON" } else { "OFF" })));
+                let on = match combine {
+                    Combine::Any => met0 || met1,
+                    Combine::All => met0 && met1,
+                };
+                let case = format!("{combine:?} of {met0} and {met1}");
+                assert_eq!(state.status.display_on, on, "{case}");
+                assert_eq!(has(&effects, DisplayCommand::BlankAll), !on, "{case}");
+
+                // A press always wakes it, until the timer runs out.
+                let effects = state.handle(Message::ButtonPress(ButtonId::NotAButton));
+                assert!(state.status.display_on, "{case}");
+                assert_eq!(has(&effects, DisplayCommand::UnBlankAll), !on, "{case}");
+                assert!(effects.contains(&Effect::StartBlankTimer(Duration::from_secs(10))));
+
+                let effects = state.handle(Message::BlankDisplays);
+                assert_eq!(state.status.display_on, on, "{case}");
+                assert_eq!(has(&effects, DisplayCommand::BlankAll), !on, "{case}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown_conditions() {
+        for combine in [Combine::Any, Combine::All] {
+            for (initially0, initially1) in
+                [(false, false), (false, true), (true, false), (true, true)]
+            {
+                let mut config = default_config();
+                config.blanking.combine = combine;
+                config.blanking.conditions[0].initially_met = initially0;
+                config.blanking.conditions[1].initially_met = initially1;
+                let mut state = start(config);
+                let case = format!("{combine:?} of {initially0} and {initially1}");
+
+                state.handle(Message::BlankDisplays);
+                let on = match combine {
+                    Combine::Any => initially0 || initially1,
+                    Combine::All => initially0 && initially1,
+                };
+                assert_eq!(state.status.display_on, on, "{case}");
+
+                // The second condition is still unknown.
+                state.handle(condition(0, "OFF"));
+                let on = match combine {
+                    Combine::Any => true,
+                    Combine::All => initially1,
+                };
+                assert_eq!(state.status.display_on, on, "{case}, first met");
+
+                state.handle(condition(0, "ON"));
+                let on = match combine {
+                    Combine::Any => initially1,
+                    Combine::All => false,
+                };
+                assert_eq!(state.status.display_on, on, "{case}, first not met");
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_conditions() {
+        // On during the day until told otherwise, but not for the light.
+        let mut state = start(default_config());
+        state.handle(Message::BlankDisplays);
+        assert!(state.status.display_on);
+
+        state.handle(condition(0, "ON"));
+        assert!(!state.status.display_on);
+
+        let mut config = default_config();
+        config.blanking.combine = Combine::All;
+        let mut state = start(config);
+        state.handle(Message::BlankDisplays);
+        assert!(!state.status.display_on);
+    }
+
+    #[test]
+    fn test_blank_dims() {
+        let mut config = default_config();
+        config.blanking.conditions.clear();
+        config.blanking.dim_brightness = 20;
+        let mut state = start(config);
+
+        let effects = state.handle(Message::BlankDisplays);
+        assert!(!state.status.display_on);
+        assert!(has(&effects, DisplayCommand::SetBrightness(20)));
+        assert!(!has(&effects, DisplayCommand::BlankAll));
+
+        let effects = state.handle(Message::ButtonPress(ButtonId::NotAButton));
+        assert!(state.status.display_on);
+        assert!(has(&effects, DisplayCommand::SetBrightness(100)));
+        assert!(has(&effects, DisplayCommand::UnBlankAll));
+    }
+
+    #[test]
+    fn test_first_press_only_wakes() {
+        let mut config = default_config();
+        config.blanking.conditions.clear();
+        let mut state = start(config);
+        state.handle(Message::MqttConnect);
+        state.handle(Message::BlankDisplays);
+        assert!(!state.status.display_on);
+
+        let commands = press_commands(&default_config(), 0, 0);
+        let effects = state.handle(Message::ButtonPress(ButtonId::Physical(0)));
+        assert!(state.status.display_on);
+        assert!(!published(&effects).contains(&commands[0]));
+
+        let effects = state.handle(Message::ButtonPress(ButtonId::Physical(0)));
+        assert!(published(&effects).contains(&commands[0]));
+    }
+
+    #[test]
+    fn test_page_stops_at_ends() {
+        let mut state = start(default_config());
+        let page = |n: usize| Some((n, 3, format!("Page {}", n + 1)));
+
+        let effects = state.handle(Message::ButtonPress(ButtonId::PageDown));
+        assert_eq!(shown_page(&effects), page(0));
+        let effects = state.handle(Message::ButtonPress(ButtonId::PageUp));
+        assert_eq!(shown_page(&effects), page(1));
+        let effects = state.handle(Message::ButtonPress(ButtonId::PageUp));
+        assert_eq!(shown_page(&effects), page(2));
+        let effects = state.handle(Message::ButtonPress(ButtonId::PageUp));
+        assert_eq!(shown_page(&effects), page(2));
+        let effects = state.handle(Message::ButtonPress(ButtonId::PageDown));
+        assert_eq!(shown_page(&effects), page(1));
+    }
+
+    #[test]
+    fn test_single_page_stays() {
+        let mut config = default_config();
+        config.pages.truncate(1);
+        let mut state = start(config);
+
+        let effects = state.handle(Message::ButtonPress(ButtonId::PageUp));
+        assert_eq!(shown_page(&effects), Some((0, 1, "Page 1".to_string())));
+        let effects = state.handle(Message::ButtonPress(ButtonId::PageDown));
+        assert_eq!(shown_page(&effects), Some((0, 1, "Page 1".to_string())));
+    }
+
+    #[test]
+    fn test_controllers_on_pages() {
+        let mut config = default_config();
+        // Controllers are numbered in order, skipping empty slots.
+        config.pages[0].slots[1] = None;
+        let mut state = start(config.clone());
+
+        assert_eq!(state.page_to_controller_id(0, 0), Some(0));
+        assert_eq!(state.page_to_controller_id(0, 1), None);
+        assert_eq!(state.page_to_controller_id(1, 0), Some(1));
+        assert_eq!(state.page_to_controller_id(2, 1), Some(4));
+        assert_eq!(state.page_to_controller_id(2, 2), None);
+        assert_eq!(state.page_to_controller_id(3, 0), None);
+
+        assert_eq!(state.controller_to_id_in_page(0), Some(0));
+        assert_eq!(state.controller_to_id_in_page(1), None);
+
+        let effects = state.handle(Message::ButtonPress(ButtonId::PageUp));
+        assert_eq!(state.controller_to_id_in_page(0), None);
+        assert_eq!(state.controller_to_id_in_page(1), Some(0));
+        assert_eq!(state.controller_to_id_in_page(2), Some(1));
+        let shows = |id_in_page: usize, name: &str| {
+            effects.iter().any(|effect| {
+                matches!(effect, Effect::Display(DisplayCommand::DisplayState(_, _, id, n))
+                    if *id == id_in_page && n == name)
+            })
+        };
+        assert!(shows(0, "Brian Fan"));
+        assert!(shows(1, "Passage"));
+
+        // The buttons on the page go to its controllers.
+        state.handle(Message::MqttConnect);
+        let effects = state.handle(Message::ButtonPress(ButtonId::Physical(1)));
+        let sent = published(&effects);
+        for command in press_commands(&config, 1, 1) {
+            assert!(sent.contains(&command));
+        }
+
+        // An empty slot only says it was pressed.
+        let effects = state.handle(Message::ButtonPress(ButtonId::PageDown));
+        assert!(has(&effects, DisplayCommand::DisplayNone(1)));
+        let effects = state.handle(Message::ButtonPress(ButtonId::Physical(1)));
+        let button = config::get_button_topic("test", 1);
+        assert_eq!(published(&effects), vec![(button, "press".to_string())]);
+    }
+
+    #[test]
+    fn test_queued_presses_replayed() {
+        let mut config = default_config();
+        let slots = &mut config.pages[0].slots;
+        slots[0].as_mut().unwrap().offline = OfflinePolicy::Replay;
+        slots[1].as_mut().unwrap().offline = OfflinePolicy::ReplayIfYounger { max_age_secs: 0 };
+        config.pages[1].slots[0].as_mut().unwrap().offline = OfflinePolicy::Drop;
+        let mut state = start(config.clone());
+
+        let effects = state.handle(Message::ButtonPress(ButtonId::Controller(0)));
+        assert!(published(&effects).is_empty());
+        assert!(has(&effects, DisplayCommand::ButtonQueued(0, true)));
+
+        let effects = state.handle(Message::ButtonPress(ButtonId::Controller(1)));
+        assert!(has(&effects, DisplayCommand::ButtonQueued(1, true)));
+
+        // Dropped rather than queued.
+        let effects = state.handle(Message::ButtonPress(ButtonId::Controller(2)));
+        assert!(published(&effects).is_empty());
+        assert_eq!(state.queue.len(), 2);
+
+        let effects = state.handle(Message::MqttConnect);
+        let sent = published(&effects);
+        for command in press_commands(&config, 0, 0) {
+            assert!(sent.contains(&command));
+        }
+        // Too old, and never queued.
+        for command in press_commands(&config, 0, 1) {
+            assert!(!sent.contains(&command));
+        }
+        for command in press_commands(&config, 1, 0) {
+            assert!(!sent.iter().any(|(topic, _)| *topic == command.0));
+        }
+        assert!(has(&effects, DisplayCommand::ButtonQueued(0, false)));
+        assert!(has(&effects, DisplayCommand::ButtonQueued(1, false)));
+        assert!(state.queue.is_empty());
+
+        // Only once.
+        let effects = state.handle(Message::MqttConnect);
+        assert!(!published(&effects).contains(&press_commands(&config, 0, 0)[0]));
+    }
+
+    fn config_message(data: &str) -> Message {
+        let topic = config::get_config_topic("test");
+        let payload = Payload::Text(data.to_string());
+        Message::MqttReceived(topic, payload, mqtt::Label::Config)
+    }
+
+    fn config_ack(effects: &[Effect]) -> serde_json::Value {
+        let topic = config::get_config_ack_topic("test");
+        let (_, payload) = published(effects)
+            .into_iter()
+            .find(|(t, _)| *t == topic)
+            .unwrap();
+        serde_json::from_str(&payload).unwrap()
+    }
+
+    const ONE_PAGE: &str = r#"{
+        "pages": [{
+            "title": "Only",
+            "slots": [{
+                "name": "Fan",
+                "icon": "fan",
+                "type": "switch",
+                "topic_substr": "Brian/Fan",
+                "action": "toggle"
+            }]
+        }]
+    }"#;
+
+    #[test]
+    fn test_config_reload() {
+        let mut state = start(default_config());
+        state.handle(Message::ButtonPress(ButtonId::PageUp));
+        state.handle(Message::ButtonPress(ButtonId::PageUp));
+
+        let effects = state.handle(config_message(ONE_PAGE));
+        assert_eq!(
+            config_ack(&effects),
+            serde_json::json!({"status": "accepted"})
+        );
+        assert!(effects.contains(&Effect::StoreConfig(ONE_PAGE.to_string())));
+        assert!(effects.contains(&Effect::Subscribe(state.get_subscriptions())));
+        // Back on the only page there is now.
+        assert_eq!(shown_page(&effects), Some((0, 1, "Only".to_string())));
+        assert!(has(&effects, DisplayCommand::DisplayNone(1)));
+        assert_eq!(state.controllers.len(), 1);
+        assert_eq!(state.page_to_controller_id(0, 0), Some(0));
+    }
+
+    #[test]
+    fn test_config_unchanged() {
+        let config = default_config();
+        let data = serde_json::to_string(&config).unwrap();
+        let mut state = start(config);
+
+        let effects = state.handle(config_message(&data));
+        assert_eq!(
+            config_ack(&effects),
+            serde_json::json!({"status": "accepted"})
+        );
+        assert_eq!(effects.len(), 1);
+    }
+
+    #[test]
+    fn test_config_rejected() {
+        let too_many = r#"{"pages": [{"title": "Big", "slots": [null, null, null]}]}"#;
+        for data in ["not json", r#"{"pages": []}"#, too_many] {
+            let mut state = start(default_config());
+            let effects = state.handle(config_message(data));
+            assert_eq!(config_ack(&effects)["status"], "rejected", "{data}");
+            assert_eq!(effects.len(), 1, "{data}");
+            assert_eq!(state.controllers.len(), 6);
+        }
+    }
+
+    fn command_message(data: &str) -> Message {
+        let topic = config::get_command_topic("test");
+        let payload = Payload::Text(data.to_string());
+        Message::MqttReceived(topic, payload, mqtt::Label::Command)
+    }
+
+    /// The reply to a command, and where it is in `effects`.
+    fn command_reply(effects: &[Effect]) -> (usize, serde_json::Value) {
+        let reply_topic = config::get_command_reply_topic("test");
+        effects
+            .iter()
+            .enumerate()
+            .find_map(|(index, effect)| match effect {
+                Effect::Publish { topic, payload, .. } if *topic == reply_topic => {
+                    Some((index, serde_json::from_str(payload).unwrap()))
+                }
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_command_malformed() {
+        let mut state = start(default_config());
+        let commands = [
+            "",
+            "not json",
+            r#"{"page": 1}"#,
+            r#"{"command": "explode"}"#,
+            r#"{"command": "press"}"#,
+            r#"{"command": "show_page", "page": "one"}"#,
+        ];
+        for data in commands {
+            let effects = state.handle(command_message(data));
+            let (_, reply) = command_reply(&effects);
+            assert_eq!(reply["status"], "error", "{data}");
+            assert!(reply["reason"].is_string(), "{data}");
+            assert_eq!(published(&effects).len(), 1, "{data}");
+            assert!(!effects.contains(&Effect::Restart), "{data}");
+        }
+    }
+
+    #[test]
+    fn test_command_unknown_index() {
+        let mut state = start(default_config());
+        state.handle(Message::MqttConnect);
+
+        let effects = state.handle(command_message(r#"{"command": "press", "controller": 99}"#));
+        let expected = serde_json::json!({"status": "error", "reason": "No controller 99"});
+        assert_eq!(command_reply(&effects).1, expected);
+        assert_eq!(published(&effects).len(), 1);
+
+        let effects = state.handle(command_message(r#"{"command": "show_page", "page": 3}"#));
+        let expected = serde_json::json!({"status": "error", "reason": "No page 3"});
+        assert_eq!(command_reply(&effects).1, expected);
+        assert_eq!(shown_page(&effects), None);
+
+        let effects = state.handle(command_message(r#"{"command": "show_page", "page": 2}"#));
+        assert_eq!(
+            command_reply(&effects).1,
+            serde_json::json!({"status": "ok"})
+        );
+        assert_eq!(shown_page(&effects), Some((2, 3, "Page 3".to_string())));
+    }
+
+    #[test]
+    fn test_command_restart_after_reply() {
+        let mut state = start(default_config());
+        state.handle(Message::MqttConnect);
+
+        let effects = state.handle(command_message(r#"{"command": "restart"}"#));
+        let (reply_at, reply) = command_reply(&effects);
+        assert_eq!(reply, serde_json::json!({"status": "ok"}));
+        let restart_at = effects.iter().position(|e| *e == Effect::Restart);
+        assert_eq!(restart_at, Some(reply_at + 1));
+    }
+}
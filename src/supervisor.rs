@@ -0,0 +1,238 @@
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Result;
+use log::*;
+use serde::Serialize;
+
+use crate::hardware;
+use crate::messages;
+use crate::messages::Message;
+
+/// Wait this long before the first restart, doubling each time after.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Reboot rather than restart the worker once it has failed this many
+/// times in a row.
+const MAX_FAILURES: u32 = 5;
+
+/// A worker that ran for this long before failing was working, so its
+/// failures no longer count as being in a row.
+const HEALTHY_AFTER: Duration = Duration::from_secs(300);
+
+/// Reported to the main thread each time a worker fails.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Failure {
+    pub worker: String,
+    pub error: String,
+    pub failures: u32,
+    /// None if the worker is not restarted.
+    pub retry_in_secs: Option<u64>,
+    /// The device reboots rather than retrying.
+    pub rebooting: bool,
+}
+
+/// An error that restarting the worker, or the device, won't fix, such as
+/// bad configuration. The worker is stopped instead.
+///
+/// It is still found when wrapped with context.
+#[derive(Debug)]
+pub struct Permanent(pub String);
+
+impl fmt::Display for Permanent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Permanent {}
+
+/// Whether `err` is, or was caused by, a [`Permanent`] error.
+pub fn is_permanent(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Permanent>().is_some()
+}
+
+fn get_backoff(failures: u32) -> Duration {
+    let backoff = MIN_BACKOFF.saturating_mul(1 << failures.saturating_sub(1).min(16));
+    backoff.min(MAX_BACKOFF)
+}
+
+/// What to do with a worker that has failed.
+#[derive(Debug, Eq, PartialEq)]
+enum Restart {
+    /// Leave it stopped, as restarting won't help.
+    Stop,
+    /// Run it again after waiting this long.
+    Retry(Duration),
+    /// Reboot the device.
+    Reboot,
+}
+
+/// Decide what to do with a worker that failed after running for `ran_for`,
+/// having already failed `failures` times in a row. Also returns the
+/// failures in a row, counting this one.
+fn get_restart(failures: u32, ran_for: Duration, permanent: bool) -> (u32, Restart) {
+    if permanent {
+        return (failures.saturating_add(1), Restart::Stop);
+    }
+
+    let failures = if ran_for >= HEALTHY_AFTER {
+        1
+    } else {
+        failures.saturating_add(1)
+    };
+    if failures >= MAX_FAILURES {
+        (failures, Restart::Reboot)
+    } else {
+        (failures, Restart::Retry(get_backoff(failures)))
+    }
+}
+
+/// Run `worker` on this thread until it returns `Ok` or a [`Permanent`]
+/// error, restarting it with backoff each time it returns any other error.
+///
+/// Anything the worker needs to keep across restarts, such as its receiver,
+/// should be owned outside of it.
+pub fn run<F>(name: &str, tx: &messages::Sender, mut worker: F)
+where
+    F: FnMut() -> Result<()>,
+{
+    let mut failures: u32 = 0;
+
+    loop {
+        let started = Instant::now();
+        let err = match worker() {
+            Ok(()) => {
+                info!("Worker {name} finished");
+                return;
+            }
+            Err(err) => err,
+        };
+
+        let restart;
+        (failures, restart) = get_restart(failures, started.elapsed(), is_permanent(&err));
+        if restart == Restart::Stop {
+            error!("Worker {name} cannot recover, stopping it: {err:?}");
+        } else {
+            error!("Worker {name} failed, {failures} times in a row: {err:?}");
+        }
+
+        let retry_in_secs = match restart {
+            Restart::Retry(retry_in) => Some(retry_in.as_secs()),
+            Restart::Stop | Restart::Reboot => None,
+        };
+        let failure = Failure {
+            worker: name.to_string(),
+            error: format!("{err:#}"),
+            failures,
+            retry_in_secs,
+            rebooting: restart == Restart::Reboot,
+        };
+        if tx.send(Message::WorkerFailed(failure)).is_err() {
+            error!("Could not report failure of worker {name}");
+        }
+
+        match restart {
+            Restart::Stop => return,
+            Restart::Retry(retry_in) => thread::sleep(retry_in),
+            Restart::Reboot => {
+                error!("Worker {name} keeps failing, rebooting");
+                // Give the MQTT thread a chance to send the last report.
+                thread::sleep(Duration::from_secs(1));
+                hardware::restart();
+                return;
+            }
+        }
+    }
+}
+
+/// Run `worker` on a new thread, as per [`run`].
+pub fn spawn<F>(
+    name: &str,
+    stack_size: Option<usize>,
+    tx: messages::Sender,
+    worker: F,
+) -> Result<()>
+where
+    F: FnMut() -> Result<()> + Send + 'static,
+{
+    let mut builder = thread::Builder::new().name(name.to_string());
+    if let Some(stack_size) = stack_size {
+        builder = builder.stack_size(stack_size);
+    }
+
+    let name = name.to_string();
+    builder.spawn(move || run(&name, &tx, worker))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUICKLY: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn test_backoff() {
+        let cases = [
+            (0, 1),
+            (1, 1),
+            (2, 2),
+            (3, 4),
+            (4, 8),
+            (6, 32),
+            (7, 60),
+            (17, 60),
+            (u32::MAX, 60),
+        ];
+        for (failures, secs) in cases {
+            let expected = Duration::from_secs(secs);
+            assert_eq!(get_backoff(failures), expected, "{failures} failures");
+        }
+    }
+
+    #[test]
+    fn test_restart_backs_off_then_reboots() {
+        let mut failures = 0;
+        let mut restarts = Vec::new();
+        for _ in 0..MAX_FAILURES {
+            let restart;
+            (failures, restart) = get_restart(failures, QUICKLY, false);
+            restarts.push(restart);
+        }
+
+        let retry = |secs| Restart::Retry(Duration::from_secs(secs));
+        let expected = [retry(1), retry(2), retry(4), retry(8), Restart::Reboot];
+        assert_eq!(restarts, expected);
+        assert_eq!(failures, MAX_FAILURES);
+    }
+
+    #[test]
+    fn test_restart_after_healthy_run() {
+        let restart = get_restart(MAX_FAILURES - 1, HEALTHY_AFTER, false);
+        assert_eq!(restart, (1, Restart::Retry(MIN_BACKOFF)));
+
+        let restart = get_restart(MAX_FAILURES - 1, HEALTHY_AFTER - QUICKLY, false);
+        assert_eq!(restart, (MAX_FAILURES, Restart::Reboot));
+    }
+
+    #[test]
+    fn test_permanent_stops() {
+        for failures in [0, 1, MAX_FAILURES - 1, MAX_FAILURES + 1] {
+            for ran_for in [QUICKLY, HEALTHY_AFTER] {
+                let restart = get_restart(failures, ran_for, true);
+                assert_eq!(restart, (failures + 1, Restart::Stop));
+            }
+        }
+    }
+
+    #[test]
+    fn test_permanent_found_with_context() {
+        let err = anyhow::Error::new(Permanent("bad config".to_string()));
+        assert!(is_permanent(&err.context("MQTT connect failed")));
+        assert!(!is_permanent(&anyhow::anyhow!("bad config")));
+    }
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::hardware;
+
+/// Health of the remote, published periodically as one JSON document.
+#[derive(Debug, Serialize)]
+pub struct Telemetry {
+    uptime_secs: u64,
+    free_heap: Option<u32>,
+    min_free_heap: Option<u32>,
+    rssi: Option<i8>,
+    mqtt_reconnects: u32,
+    /// MQTT payloads that could not be decoded, since boot.
+    mqtt_decode_failures: u32,
+    reset_reason: Option<&'static str>,
+    version: &'static str,
+}
+
+impl Telemetry {
+    pub fn collect(
+        uptime: Duration,
+        rssi: Option<i8>,
+        mqtt_reconnects: u32,
+        mqtt_decode_failures: u32,
+    ) -> Self {
+        Telemetry {
+            uptime_secs: uptime.as_secs(),
+            free_heap: hardware::get_free_heap(),
+            min_free_heap: hardware::get_min_free_heap(),
+            rssi,
+            mqtt_reconnects,
+            mqtt_decode_failures,
+            reset_reason: hardware::get_reset_reason(),
+            version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
@@ -11,6 +11,9 @@ use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::netif::*;
 use esp_idf_svc::sntp::EspSntp;
 use esp_idf_svc::wifi::*;
+use esp_idf_sys::esp;
+use esp_idf_sys::esp_wifi_sta_get_ap_info;
+use esp_idf_sys::wifi_ap_record_t;
 
 use anyhow::bail;
 use anyhow::Result;
@@ -101,3 +104,10 @@ fn wifi(
     // ping(ip_info.subnet.gateway)?;
     Ok(wifi)
 }
+
+/// Signal strength of the access point we are connected to, in dBm.
+pub fn get_rssi(_wifi: &EspWifi<'static>) -> Option<i8> {
+    let mut info = wifi_ap_record_t::default();
+    esp!(unsafe { esp_wifi_sta_get_ap_info(&mut info) }).ok()?;
+    Some(info.rssi)
+}